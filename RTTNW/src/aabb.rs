@@ -0,0 +1,59 @@
+use std::ops::Range;
+use ultraviolet::Vec3;
+use crate::ray::Ray;
+
+// an axis aligned bounding box. the empty box has min above max, so it grows to whatever
+// it's joined with and no ray hits it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+
+    pub fn empty() -> Self {
+        Aabb { min: Vec3::broadcast(f32::INFINITY), max: Vec3::broadcast(f32::NEG_INFINITY) }
+    }
+
+    pub fn around(centre: Vec3, radius: f32) -> Self {
+        Aabb { min: centre - Vec3::broadcast(radius), max: centre + Vec3::broadcast(radius) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: self.min.min_by_component(other.min), max: self.max.max_by_component(other.max) }
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        std::array::from_fn(|i| Vec3::new(
+            if i & 1 == 0 { self.min.x } else { self.max.x },
+            if i & 2 == 0 { self.min.y } else { self.max.y },
+            if i & 4 == 0 { self.min.z } else { self.max.z },
+        ))
+    }
+
+    pub fn hit(&self, ray: &Ray, t_interval: Range<f32>) -> bool {
+        // slab test: the ray is inside the box where it's between all three pairs of planes
+
+        let (mut t_min, mut t_max) = (t_interval.start, t_interval.end);
+
+        for axis in 0..3 {
+            let inv_d = 1.0 / ray.direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
+            let (t0, t1) = if inv_d < 0.0 { (t1, t0) } else { (t0, t1) };
+
+            // max and min skip the NaN of a ray running along one of the planes
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min { return false }
+        }
+
+        true
+    }
+
+}
@@ -0,0 +1,396 @@
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::ops::{Add, Mul, Range, Sub};
+use std::str::FromStr;
+use ultraviolet::{Bivec3, Rotor3, Slerp, Vec3};
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+
+// bounds are swept by placing the object this many times between each pair of keys
+const SWEEP_STEPS: usize = 16;
+// keys to a turn of a camera orbit
+const ORBIT_STEPS: f32 = 36.0;
+
+// scaling, then turning, then moving an object from its own space into the world
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Rotor3,
+    // per axis, non-zero. negative scales mirror
+    pub scale: Vec3,
+}
+
+// where an object is at a time, in the same time units as the shutter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: Transform,
+}
+
+// how an object gets from one key to the next
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    // straight between keys, turning at a steady rate. the object jerks at each key
+    Linear,
+    // a cubic Bezier through the keys, with control points from the keys either side
+    // (as Catmull-Rom), so it moves smoothly through them
+    Bezier,
+}
+
+// where the camera is and how it's set up at a time, with vfov in radians
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraKey {
+    pub time: f32,
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub vfov: f32,
+    pub focus_distance: f32,
+}
+
+// the camera moving through keys, for fly-throughs. like objects, it holds still before
+// the first key and after the last
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keys: Vec<CameraKey>,
+    interpolation: Interpolation,
+}
+
+// any object moved, turned and scaled by keyframes. before the first key and after the
+// last it stays where that key puts it. rotations between keys take the shortest way
+// round, so keys more than half a turn apart need keys between them
+pub struct Animated {
+    object: Box<dyn Hittable>,
+    keys: Vec<Keyframe>,
+    interpolation: Interpolation,
+    // world bounds over the whole animation
+    bounds: Aabb,
+}
+
+impl Transform {
+
+    pub fn identity() -> Self {
+        Transform { translation: Vec3::zero(), rotation: Rotor3::identity(), scale: Vec3::one() }
+    }
+
+    fn point_to_world(&self, p: Vec3) -> Vec3 {
+        self.rotation * (p * self.scale) + self.translation
+    }
+
+    fn ray_to_local(&self, ray: &Ray) -> Ray {
+        // the inverse transform keeps the distances along the ray, so hits found in the
+        // object's space are at the same t in the world
+        let inverse = self.rotation.reversed();
+        Ray::new(
+            inverse * (ray.origin - self.translation) / self.scale,
+            inverse * ray.direction / self.scale,
+            ray.time,
+        )
+    }
+
+    fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        // by the inverse transpose, so normals stay at right angles to scaled surfaces
+        (self.rotation * (normal / self.scale)).normalized()
+    }
+
+}
+
+impl Animated {
+
+    pub fn new(object: Box<dyn Hittable>, mut keys: Vec<Keyframe>, interpolation: Interpolation) -> Self {
+        if keys.is_empty() {
+            keys.push(Keyframe { time: 0.0, transform: Transform::identity() });
+        }
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut animated = Animated { object, keys, interpolation, bounds: Aabb::empty() };
+        animated.bounds = animated.swept_bounds();
+        animated
+    }
+
+    fn transform_at(&self, time: f32) -> Transform {
+
+        let span = match span(&self.keys, |k| k.time, time) {
+            Place::Held(key) => return key.transform,
+            Place::Between(span) => span,
+        };
+        let [t0, t1, t2, t3] = span.keys.map(|k| k.transform);
+        let blend_of = |f: fn(&Transform) -> Vec3| {
+            blend(self.interpolation, [f(&t0), f(&t1), f(&t2), f(&t3)], span.times, span.s)
+        };
+
+        let rotation = match self.interpolation {
+            Interpolation::Linear => t1.rotation.slerp(t2.rotation, span.s).normalized(),
+            Interpolation::Bezier => {
+                // rotors r and -r are the same turn, so line the neighbours up first
+                let align = |r: Rotor3| if r.dot(t1.rotation) < 0.0 { r * -1.0 } else { r };
+                let [r0, r1, r2, r3] = [t0, t1, t2, t3].map(|t| align(t.rotation));
+                let (out_weight, in_weight) = tangent_weights(span.times);
+                let rotations = [
+                    r1,
+                    (r1 + (r2 - r0) * out_weight).normalized(),
+                    (r2 - (r3 - r1) * in_weight).normalized(),
+                    r2,
+                ];
+                bezier(rotations, span.s, |p, q, s| p.slerp(q, s).normalized())
+            }
+        };
+
+        Transform { translation: blend_of(|t| t.translation), rotation, scale: blend_of(|t| t.scale) }
+
+    }
+
+    fn swept_bounds(&self) -> Aabb {
+        // the object always sits in its own bounds, transformed, which are the hull of
+        // the box's corners. between two places the corners move along a path of length
+        // L and stay within L / 2 of one end, and with the places close together L is
+        // under twice the straight distance, so padding by that distance covers the path
+
+        let local = self.object.bounding_box();
+        if local.is_empty() { return local }
+
+        let corners = local.corners();
+        let place = |time: f32| {
+            let transform = self.transform_at(time);
+            corners.map(|c| transform.point_to_world(c))
+        };
+
+        let mut times = vec![self.keys[0].time];
+        for pair in self.keys.windows(2) {
+            let (start, end) = (pair[0].time, pair[1].time);
+            times.extend((1..=SWEEP_STEPS).map(|i| start + (end - start) * i as f32 / SWEEP_STEPS as f32));
+        }
+
+        let mut bounds = Aabb::empty();
+        let mut previous = place(times[0]);
+        previous.iter().for_each(|&c| bounds = bounds.union(&Aabb::around(c, 0.0)));
+
+        for &time in &times[1..] {
+            let next = place(time);
+            for (&a, &b) in previous.iter().zip(&next) {
+                let pad = (b - a).mag();
+                bounds = bounds.union(&Aabb::around(a, pad)).union(&Aabb::around(b, pad));
+            }
+            previous = next;
+        }
+
+        bounds
+    }
+
+}
+
+// where a time falls among a list of keys sorted by time
+enum Place<'a, K> {
+    // before the first key or after the last, held there
+    Held(&'a K),
+    Between(Span<'a, K>),
+}
+
+// the two keys a time is between and those either side of them, repeating the end keys,
+// with their times and how far the time is from the second key to the third
+struct Span<'a, K> {
+    keys: [&'a K; 4],
+    times: [f32; 4],
+    s: f32,
+}
+
+fn span<K>(keys: &[K], key_time: impl Fn(&K) -> f32, time: f32) -> Place<'_, K> {
+
+    let i = keys.partition_point(|k| key_time(k) <= time);
+    if i == 0 { return Place::Held(&keys[0]) }
+    if i == keys.len() { return Place::Held(&keys[i - 1]) }
+
+    let keys = [&keys[i.saturating_sub(2)], &keys[i - 1], &keys[i], &keys[(i + 1).min(keys.len() - 1)]];
+    let times = keys.map(&key_time);
+    Place::Between(Span { keys, times, s: (time - times[1]) / (times[2] - times[1]) })
+
+}
+
+fn tangent_weights(times: [f32; 4]) -> (f32, f32) {
+    // how far along the tangents at the second and third keys (taken from the keys either
+    // side of them) the inner Bezier control points go: a third of the way, scaled to
+    // the length of this span
+    let [t0, t1, t2, t3] = times;
+    ((t2 - t1) / (t2 - t0) / 3.0, (t2 - t1) / (t3 - t1) / 3.0)
+}
+
+fn blend<T>(interpolation: Interpolation, p: [T; 4], times: [f32; 4], s: f32) -> T
+where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T> {
+    // a value between the second and third of p
+
+    let mix = |a: T, b: T, s: f32| a * (1.0 - s) + b * s;
+
+    match interpolation {
+        Interpolation::Linear => mix(p[1], p[2], s),
+        Interpolation::Bezier => {
+            let (out_weight, in_weight) = tangent_weights(times);
+            let controls = [p[1], p[1] + (p[2] - p[0]) * out_weight, p[2] - (p[3] - p[1]) * in_weight, p[2]];
+            bezier(controls, s, mix)
+        }
+    }
+}
+
+impl CameraPath {
+
+    pub fn new(mut keys: Vec<CameraKey>, interpolation: Interpolation) -> Self {
+        // needs at least one key
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        CameraPath { keys, interpolation }
+    }
+
+    pub fn orbit(start: CameraKey, up: Vec3, period: f32, duration: f32) -> Self {
+        // look_from circling look_at about up, a turn every period, from start.time to past
+        // start.time + duration. Bezier keys close enough together follow the circle to
+        // within a hair, and an extra key before and after keeps the speed steady to the ends
+
+        let plane = Bivec3::from_normalized_axis(up.normalized());
+        let steps = (duration / period * ORBIT_STEPS).ceil().max(1.0) as i32;
+
+        let keys = (-1..=steps + 1).map(|i| {
+            let turn = i as f32 / ORBIT_STEPS;
+            let offset = Rotor3::from_angle_plane(turn * 2.0 * PI, plane) * (start.look_from - start.look_at);
+            CameraKey { time: start.time + turn * period, look_from: start.look_at + offset, ..start }
+        }).collect();
+
+        CameraPath::new(keys, Interpolation::Bezier)
+    }
+
+    pub fn at(&self, time: f32) -> CameraKey {
+
+        let span = match span(&self.keys, |k| k.time, time) {
+            Place::Held(key) => return CameraKey { time, ..*key },
+            Place::Between(span) => span,
+        };
+
+        let blend_of = |f: fn(&CameraKey) -> Vec3| {
+            blend(self.interpolation, span.keys.map(f), span.times, span.s)
+        };
+        let blend_value = |f: fn(&CameraKey) -> f32| {
+            blend(self.interpolation, span.keys.map(f), span.times, span.s)
+        };
+
+        CameraKey {
+            time,
+            look_from: blend_of(|k| k.look_from),
+            look_at: blend_of(|k| k.look_at),
+            vfov: blend_value(|k| k.vfov),
+            focus_distance: blend_value(|k| k.focus_distance),
+        }
+
+    }
+
+}
+
+pub fn turntable(object: Box<dyn Hittable>, centre: Vec3, up: Vec3, period: f32, duration: f32) -> Animated {
+    // turns object about the axis through centre along up, once every period, with keys
+    // from time 0 to past duration. slerp turns at a steady rate between keys a quarter
+    // turn apart. the turn is about the object's own origin, so centre is moved there first
+
+    let centred = Animated::new(object, vec![Keyframe {
+        time: 0.0,
+        transform: Transform { translation: -centre, ..Transform::identity() },
+    }], Interpolation::Linear);
+
+    let quarters = (4.0 * duration / period).ceil().max(1.0) as u32;
+    let plane = Bivec3::from_normalized_axis(up.normalized());
+
+    let keys = (0..=quarters).map(|k| Keyframe {
+        time: k as f32 * period / 4.0,
+        transform: Transform {
+            translation: centre,
+            rotation: Rotor3::from_angle_plane(k as f32 * FRAC_PI_2, plane),
+            scale: Vec3::one(),
+        },
+    }).collect();
+
+    Animated::new(Box::new(centred), keys, Interpolation::Linear)
+}
+
+fn bezier<T: Copy>(p: [T; 4], s: f32, mix: impl Fn(T, T, f32) -> T) -> T {
+    // de Casteljau's construction, which works for rotations too given slerp to mix them
+    let [a, b, c] = [mix(p[0], p[1], s), mix(p[1], p[2], s), mix(p[2], p[3], s)];
+    let [d, e] = [mix(a, b, s), mix(b, c, s)];
+    mix(d, e, s)
+}
+
+impl Hittable for Animated {
+    fn hit(&self, ray: &Ray, t_interval: Range<f32>) -> Option<HitRecord> {
+
+        if !self.bounds.hit(ray, t_interval.clone()) { return None }
+
+        let transform = self.transform_at(ray.time);
+        let mut hit = self.object.hit(&transform.ray_to_local(ray), t_interval)?;
+
+        // front_face carries over, as the transform keeps the sign of normal . direction
+        hit.point = ray.at(hit.time);
+        hit.normal = transform.normal_to_world(hit.normal);
+
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn hit_cost(&self, ray: &Ray, t_interval: Range<f32>) -> u32 {
+        if !self.bounds.hit(ray, t_interval.clone()) { return 1 }
+
+        let transform = self.transform_at(ray.time);
+        1 + self.object.hit_cost(&transform.ray_to_local(ray), t_interval)
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Interpolation::Linear),
+            "bezier" => Ok(Interpolation::Bezier),
+            _ => Err(format!("unknown interpolation '{s}'"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+    use ultraviolet::Bivec3;
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn swept_bounds_hold_the_object_throughout() {
+        // a sphere off to the side of its origin, swung round and moved about by keys a
+        // third of a turn apart, with Bezier keys overshooting between them
+        let material = Arc::new(Lambertian::new(Vec3::one()));
+        let plane = Bivec3::from_normalized_axis(Vec3::new(0.3, 1.0, 0.2).normalized());
+        let keys: Vec<Keyframe> = (0..5).map(|k| Keyframe {
+            time: k as f32 * 0.5,
+            transform: Transform {
+                translation: Vec3::new(k as f32, (k % 2) as f32, 0.0),
+                rotation: Rotor3::from_angle_plane(k as f32 * 2.0 * PI / 3.0, plane),
+                scale: Vec3::new(1.0, 0.5 + k as f32 * 0.25, 1.0),
+            },
+        }).collect();
+
+        for interpolation in [Interpolation::Linear, Interpolation::Bezier] {
+            let sphere = Sphere::new(Vec3::new(2.0, 0.5, 0.0), 0.5, material.clone());
+            let local = sphere.bounding_box();
+            let animated = Animated::new(Box::new(sphere), keys.clone(), interpolation);
+            let bounds = animated.bounding_box();
+
+            for i in 0..=10_000 {
+                let time = -0.5 + 3.5 * i as f32 / 10_000.0;
+                let transform = animated.transform_at(time);
+
+                for corner in local.corners().map(|c| transform.point_to_world(c)) {
+                    let outside = (bounds.min - corner).max_by_component(corner - bounds.max);
+                    assert!(outside.component_max() <= 1e-4, "{interpolation:?} corner {corner:?} at {time} \
+                        is outside {bounds:?}");
+                }
+            }
+        }
+    }
+}
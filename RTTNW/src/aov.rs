@@ -0,0 +1,256 @@
+use std::ops::Range;
+use std::str::FromStr;
+use image::{Rgb, RgbImage};
+use itertools::iproduct;
+use ultraviolet::Vec3;
+use crate::tonemap::{srgb_encode, ToneMap};
+
+// arbitrary output variables: what the camera rays first hit, and the beauty image split
+// by how the light got there, kept alongside the beauty image for compositing and
+// denoising. each is the plain mean of the samples taken in a pixel, not filtered
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    // the colour of the first surface hit, 1 for glass, or the sky's radiance clamped to 1
+    Albedo,
+    // the shading normal of the first hit, facing the camera, in world space
+    Normal,
+    Position,
+    // distance from the camera along the view axis, or along the ray for fisheye and
+    // equirectangular views. 0 where nothing was hit
+    Depth,
+    // materials by the number the scene gave them, from 1, and objects by their place in
+    // the list they're in, from 1. 0 is the sky. every pixel takes its first sample's
+    MaterialId,
+    ObjectId,
+    // light reaching the camera off at most one surface straight from the sky or the
+    // sun, and the rest of the beauty image
+    Direct,
+    Indirect,
+}
+
+impl Aov {
+
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo, Aov::Normal, Aov::Position, Aov::Depth,
+        Aov::MaterialId, Aov::ObjectId, Aov::Direct, Aov::Indirect,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        // as an exr layer and in file names
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase().replace('-', "_");
+        Aov::ALL.into_iter()
+            .find(|aov| aov.name() == name)
+            .ok_or_else(|| format!("unknown aov '{s}'"))
+    }
+}
+
+// the aovs of one camera sample
+#[derive(Clone, Copy, Default)]
+pub struct AovSample {
+    pub albedo: Vec3,
+    pub normal: Vec3,
+    pub position: Vec3,
+    pub depth: f32,
+    // 0 for the sky
+    pub material: u32,
+    pub object: Option<u32>,
+    pub direct: Vec3,
+    pub indirect: Vec3,
+}
+
+#[derive(Clone, Copy, Default)]
+struct AovPixel {
+    count: u32,
+    albedo: Vec3,
+    normal: Vec3,
+    position: Vec3,
+    depth: f32,
+    material: u32,
+    object: Option<u32>,
+    direct: Vec3,
+    indirect: Vec3,
+}
+
+// per pixel sums of the aov samples, over the whole frame or one tile of it
+pub struct AovBuffer {
+    x: Range<u32>,
+    y: Range<u32>,
+    pixels: Vec<AovPixel>,
+}
+
+impl AovPixel {
+
+    fn add(&mut self, s: &AovSample) {
+        if self.count == 0 {
+            self.material = s.material;
+            self.object = s.object;
+        }
+        self.count += 1;
+        self.albedo += s.albedo;
+        self.normal += s.normal;
+        self.position += s.position;
+        self.depth += s.depth;
+        self.direct += s.direct;
+        self.indirect += s.indirect;
+    }
+
+    fn mean(&self, v: Vec3) -> Vec3 {
+        if self.count > 0 { v / self.count as f32 } else { Vec3::zero() }
+    }
+
+}
+
+impl AovBuffer {
+
+    pub fn new(x: Range<u32>, y: Range<u32>) -> Self {
+        let pixels = vec![AovPixel::default(); x.len() * y.len()];
+        AovBuffer { x, y, pixels }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y - self.y.start) * self.x.len() as u32 + x - self.x.start) as usize
+    }
+
+    pub fn add_sample(&mut self, (x, y): (u32, u32), sample: &AovSample) {
+        let i = self.index(x, y);
+        self.pixels[i].add(sample);
+    }
+
+    pub fn merge_tile(&mut self, tile: AovBuffer) {
+        // a tile covers its pixels alone, so its sums simply add on. a pixel's first
+        // sample is the one from its earliest tile
+
+        for ((y, x), src) in iproduct!(tile.y.clone(), tile.x.clone()).zip(tile.pixels) {
+            if src.count == 0 { continue }
+
+            let i = self.index(x, y);
+            let dst = &mut self.pixels[i];
+            if dst.count == 0 {
+                dst.material = src.material;
+                dst.object = src.object;
+            }
+            dst.count += src.count;
+            dst.albedo += src.albedo;
+            dst.normal += src.normal;
+            dst.position += src.position;
+            dst.depth += src.depth;
+            dst.direct += src.direct;
+            dst.indirect += src.indirect;
+        }
+    }
+
+    pub fn values(&self, aov: Aov, x: Range<u32>, y: Range<u32>) -> Vec<Vec3> {
+        // the linear values of the pixels in x * y, row-major from the top left. scalars
+        // fill all three channels
+
+        iproduct!(y, x)
+            .map(|(y, x)| {
+                let p = &self.pixels[self.index(x, y)];
+                match aov {
+                    Aov::Albedo => p.mean(p.albedo),
+                    Aov::Normal => {
+                        let n = p.mean(p.normal);
+                        if n.mag_sq() > 0.0 { n.normalized() } else { n }
+                    }
+                    Aov::Position => p.mean(p.position),
+                    Aov::Depth => Vec3::broadcast(p.mean(Vec3::broadcast(p.depth)).x),
+                    Aov::MaterialId => Vec3::broadcast(p.material as f32),
+                    Aov::ObjectId => Vec3::broadcast(p.object.map_or(0.0, |i| (i + 1) as f32)),
+                    Aov::Direct => p.mean(p.direct),
+                    Aov::Indirect => p.mean(p.indirect),
+                }
+            })
+            .collect()
+    }
+
+}
+
+pub fn aov_image(aov: Aov, width: u32, height: u32, values: &[Vec3], exposure: f32, tone_map: ToneMap) -> RgbImage {
+    // an 8 bit picture of the values of an aov. lighting is tone mapped like the beauty
+    // image, normals map -1..1 to 0..1, positions and depth are scaled to the range in
+    // the image, and ids get a colour each
+
+    let (min, max) = values.iter().fold(
+        (Vec3::broadcast(f32::INFINITY), Vec3::broadcast(f32::NEG_INFINITY)),
+        |(min, max), v| (min.min_by_component(*v), max.max_by_component(*v)));
+    let span = (max - min).max_by_component(Vec3::broadcast(1e-6));
+
+    let to_8bit = |v: Vec3| {
+        let v = v.clamped(Vec3::zero(), Vec3::one()) * 255.0;
+        Rgb([v.x.round() as u8, v.y.round() as u8, v.z.round() as u8])
+    };
+    let encoded = |mut v: Vec3| { v.apply(srgb_encode); v };
+    let scale = exposure.exp2();
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let v = values[(y * width + x) as usize];
+        match aov {
+            Aov::Albedo => to_8bit(encoded(v)),
+            Aov::Normal => to_8bit(v * 0.5 + Vec3::broadcast(0.5)),
+            Aov::Position => to_8bit((v - min) / span),
+            Aov::Depth => to_8bit(v / max.x.max(1e-6)),
+            Aov::MaterialId | Aov::ObjectId => to_8bit(id_colour(v.x as u32)),
+            Aov::Direct | Aov::Indirect => to_8bit(encoded(tone_map.apply(scale * v))),
+        }
+    })
+}
+
+fn id_colour(id: u32) -> Vec3 {
+    // a bright colour for each id from a hash of it, black for 0
+    if id == 0 { return Vec3::zero() }
+
+    let mut h = id.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+
+    let channel = |shift: u32| 0.25 + 0.75 * ((h >> shift) & 0xff) as f32 / 255.0;
+    Vec3::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_accumulate_means_and_first_ids() {
+        let mut buffer = AovBuffer::new(0..2, 0..1);
+
+        // two samples of an object in one tile, and the sky in another
+        let mut hits = AovBuffer::new(0..1, 0..1);
+        let first = AovSample { depth: 1.0, normal: Vec3::unit_z(), material: 3, object: Some(2), ..Default::default() };
+        let second = AovSample { depth: 3.0, normal: Vec3::unit_y(), material: 5, object: Some(4), ..first };
+        hits.add_sample((0, 0), &first);
+        hits.add_sample((0, 0), &second);
+        let mut sky = AovBuffer::new(1..2, 0..1);
+        sky.add_sample((1, 0), &AovSample::default());
+
+        buffer.merge_tile(hits);
+        buffer.merge_tile(sky);
+
+        let values = |aov| buffer.values(aov, 0..2, 0..1);
+        assert_eq!(values(Aov::Depth), [Vec3::broadcast(2.0), Vec3::zero()]);
+        assert!((values(Aov::Normal)[0] - Vec3::new(0.0, 1.0, 1.0).normalized()).mag() < 1e-6);
+        assert_eq!(values(Aov::Normal)[1], Vec3::zero());
+        assert_eq!(values(Aov::MaterialId), [Vec3::broadcast(3.0), Vec3::zero()]);
+        assert_eq!(values(Aov::ObjectId), [Vec3::broadcast(3.0), Vec3::zero()]);
+    }
+}
@@ -0,0 +1,169 @@
+use std::f32::consts::PI;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use ultraviolet::Vec3;
+use crate::error::RenderError;
+use crate::ray::sample_unit_disk;
+
+// sensor height of a full frame 35mm camera, which focal lengths are taken against
+const SENSOR_HEIGHT_MM: f32 = 24.0;
+// scene units are metres
+const MM_PER_UNIT: f32 = 1000.0;
+
+// a photographic lens, in place of a field of view and defocus angle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lens {
+    // in mm
+    pub focal_length: f32,
+    pub f_number: f32,
+}
+
+impl Lens {
+
+    pub fn vfov(&self) -> f32 {
+        // vertical field of view on a full frame sensor, in radians
+        2.0 * (SENSOR_HEIGHT_MM / (2.0 * self.focal_length)).atan()
+    }
+
+    pub fn aperture_radius(&self) -> f32 {
+        // in scene units
+        self.focal_length / self.f_number / 2.0 / MM_PER_UNIT
+    }
+
+}
+
+// the shape of the lens opening, which out of focus highlights take on
+#[derive(Clone, Debug)]
+pub enum ApertureShape {
+    Circle,
+    // a regular polygon with a corner on the unit circle at `rotation` radians from the
+    // right, as cut by `blades` straight aperture blades
+    Polygon { blades: u32, rotation: f32 },
+    // a grey image stretched over the square around the aperture, as its transmission
+    Mask(Arc<ApertureMask>),
+}
+
+impl ApertureShape {
+
+    pub fn sample(&self, u: (f32, f32)) -> Vec3 {
+        // maps a 2D sample onto the aperture, within the unit square around the lens
+        // centre (z = 0). bright parts of a mask are sampled more, rather than weighted
+
+        match self {
+            ApertureShape::Circle => sample_unit_disk(u),
+            ApertureShape::Polygon { blades, rotation } => {
+                // pick one of the triangles fanning out from the centre, then a point in it
+                let n = *blades as f32;
+                let i = (u.0 * n).floor().min(n - 1.0);
+                let u0 = u.0 * n - i;
+
+                let corner = |k: f32| {
+                    let angle = rotation + 2.0 * PI * k / n;
+                    Vec3::new(angle.cos(), angle.sin(), 0.0)
+                };
+
+                // uniform in the triangle (0, a, b)
+                let (a, b) = (corner(i), corner(i + 1.0));
+                let s = u0.sqrt();
+                a * (s * (1.0 - u.1)) + b * (s * u.1)
+            }
+            ApertureShape::Mask(mask) => mask.sample(u),
+        }
+    }
+
+}
+
+impl FromStr for ApertureShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "circle", or "polygon:blades" with an optional ":rotation" in degrees
+
+        let parts: Vec<&str> = s.split(':').map(str::trim).collect();
+
+        match parts[..] {
+            [name] if name.eq_ignore_ascii_case("circle") => Ok(ApertureShape::Circle),
+            [name, blades, ref rotation @ ..] if name.eq_ignore_ascii_case("polygon") && rotation.len() <= 1 => {
+                let blades: u32 = blades.parse().map_err(|_| format!("bad blade count in aperture '{s}'"))?;
+                if blades < 3 { return Err(format!("aperture '{s}' needs at least 3 blades")) }
+
+                let rotation: f32 = match rotation.first() {
+                    Some(r) => r.parse().map_err(|_| format!("bad rotation in aperture '{s}'"))?,
+                    None => 90.0,
+                };
+                Ok(ApertureShape::Polygon { blades, rotation: rotation.to_radians() })
+            }
+            _ => Err(format!("unknown aperture '{s}'"))
+        }
+    }
+}
+
+// an aperture image, kept as the cumulative distributions that sample it
+#[derive(Debug)]
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    // running total of the transmission down the rows, from 0 to 1
+    row_cdf: Vec<f32>,
+    // running total along each row, from 0 to 1, width + 1 values per row
+    col_cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+
+    pub fn open(path: &Path) -> Result<Self, RenderError> {
+
+        let image = image::open(path)?.to_luma32f();
+        let (width, height) = image.dimensions();
+
+        let mut row_cdf = vec![0.0];
+        let mut col_cdf = Vec::with_capacity(((width + 1) * height) as usize);
+
+        for row in image.rows() {
+            let start = col_cdf.len();
+            let mut total = 0.0;
+            col_cdf.push(0.0);
+            for p in row {
+                total += p[0].max(0.0);
+                col_cdf.push(total);
+            }
+            if total > 0.0 {
+                col_cdf[start..].iter_mut().for_each(|c| *c /= total);
+            }
+            row_cdf.push(row_cdf.last().unwrap() + total);
+        }
+
+        let total = *row_cdf.last().unwrap();
+        if total <= 0.0 {
+            return Err(RenderError::InvalidConfig(format!("aperture mask {} is black", path.display())));
+        }
+        row_cdf.iter_mut().for_each(|c| *c /= total);
+
+        Ok(ApertureMask { width, height, row_cdf, col_cdf })
+
+    }
+
+    fn sample(&self, u: (f32, f32)) -> Vec3 {
+        // picks a row by its transmission, then a pixel along it. where u falls inside the
+        // chosen row and pixel places the point within the pixel
+
+        let pick = |cdf: &[f32], u: f32| {
+            let i = (cdf.partition_point(|&c| c <= u).max(1) - 1).min(cdf.len() - 2);
+            let width = cdf[i + 1] - cdf[i];
+            let t = if width > 0.0 { ((u - cdf[i]) / width).clamp(0.0, 1.0) } else { 0.5 };
+            (i as u32, t)
+        };
+
+        let (row, ty) = pick(&self.row_cdf, u.1);
+        let start = (row * (self.width + 1)) as usize;
+        let (col, tx) = pick(&self.col_cdf[start..start + self.width as usize + 1], u.0);
+
+        Vec3::new(
+            2.0 * (col as f32 + tx) / self.width as f32 - 1.0,
+            1.0 - 2.0 * (row as f32 + ty) / self.height as f32,
+            0.0,
+        )
+    }
+
+}
@@ -1,244 +1,1061 @@
-use std::ops::Div;
-use std::path::PathBuf;
-use image::{RgbImage, Rgb};
-use indicatif::{ProgressBar, ProgressIterator};
-use indicatif::ProgressStyle;
+use std::f32::consts::PI;
+use std::io;
+use std::mem;
+use std::ops::{Div, Range};
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use image::{Rgb, RgbImage};
 use ultraviolet::Vec3;
 use itertools::iproduct;
-use rand::{random, random_range};
-use rayon::iter::IntoParallelIterator;
-use rayon::prelude::*;
-use crate::hittable::{Hittable, HittableList};
-use crate::ray::{random_in_unit_disk, Ray};
+use rand::{random, Rng};
 use rayon::prelude::*;
+use crate::animation::CameraKey;
+use crate::aov::{aov_image, Aov, AovBuffer, AovSample};
+use crate::aperture::{ApertureShape, Lens};
+use crate::checkpoint::{read_checkpoint, read_settings, write_checkpoint, RenderSettings};
+use crate::debug::{front_face_colour, material_colour, DebugShading};
+use crate::denoise::Denoiser;
+use crate::error::RenderError;
+use crate::film::{Film, FilmTile, Filter, PixelStats};
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::job::{JobSplit, RenderJob};
+use crate::progress::{CancelToken, FrameBuffer, ProgressTracker, RenderObserver, RenderSummary};
+use crate::output::{read_pfm, write_exr, write_hdr, write_pfm, ExrPrecision};
+use crate::ray::Ray;
+use crate::rig::{Convergence, Rig, StereoLayout};
+use crate::sampler::{hash, AdaptiveSampling, Sampler, SamplerKind};
+use crate::shutter::{Shutter, ShutterCurve};
+use crate::sky::Sky;
+use crate::tonemap::{srgb_decode, ToneMap};
+
+// edge length in pixels of the square tiles the frame is split into for rendering
+const TILE_SIZE: u32 = 16;
+// samples per pixel added by each pass of a time-budgeted render, unless set explicitly
+const BUDGET_PASS_SPP: u32 = 4;
 
 pub struct Camera {
     width: u32,
     height: u32,
-    aspect_ratio: f32,
     px_samples: u32,
-    px_samples_scale: f32,
     max_depth: u32,
-    image: RgbImage,
+    film: Film,
+
+    lens_radius: f32,
+    focus_distance: f32,
+    aperture: ApertureShape,
+    cats_eye: f32,
+    shutter: Shutter,
+    views: Vec<View>,
+
+    sky: Sky,
+    sampler: Box<dyn Sampler>,
+    exr_precision: ExrPrecision,
+    exposure: f32,
+    tone_map: ToneMap,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<ProgressiveRendering>,
+    time_limit: Option<Duration>,
+    crop: Option<Crop>,
+    job: Option<RenderJob>,
+    aovs: Vec<Aov>,
+    aov_buffer: Option<AovBuffer>,
+    denoiser: Option<Denoiser>,
+    debug: Option<DebugShading>,
+    observers: Vec<Arc<dyn RenderObserver>>,
+    cancel: Option<CancelToken>,
+
+    seed: u64,
+    sampler_kind: SamplerKind,
+    filter: Filter,
+    optics: u64,
+    checkpoint: Option<PathBuf>,
+    passes_done: u32,
 
-    defocus_angle: f32,
-    viewport_height: f32,
-    viewport_width: f32,
+}
 
-    origin: Vec3,
-    viewport_u: Vec3,
-    viewport_v: Vec3,
+impl Camera {
 
-    px_delta_u: Vec3,
-    px_delta_v: Vec3,
-    px_loc_100: Vec3,
-    u: Vec3, v: Vec3, w: Vec3,
+    fn init(cam_setup: &CameraSetup) -> Self {
+        // set up from CameraSetup::build, once the settings are validated
 
-    defocus_disc_u: Vec3,
-    defocus_disc_v: Vec3,
+        // camera setup
+        let (view_width, view_height, aspect_ratio) = cam_setup.dimensions();
+        let (width, height) = cam_setup.frame_size().expect("the frame size is validated");
+
+        let pose = (cam_setup.look_from, cam_setup.look_at, cam_setup.vertical_up);
+        let lens = (cam_setup.vfov(), aspect_ratio, cam_setup.projection);
+        let centre = View::new(cam_setup, pose, 0..view_width, 0..view_height, lens);
+
+        let views = match cam_setup.rig {
+            Rig::Mono => vec![centre],
+            Rig::Stereo { eye_separation, convergence, layout } => {
+                // the eyes sit either side of look_from along the centre view's basis
+                let offset = centre.u * (eye_separation / 2.0);
+                let target = |eye: Vec3| match convergence {
+                    Convergence::Parallel => cam_setup.look_at + (eye - cam_setup.look_from),
+                    Convergence::ToeIn => cam_setup.look_from - centre.w * cam_setup.focus_distance,
+                };
+                let right_region = match layout {
+                    StereoLayout::SideBySide => (view_width..width, 0..height),
+                    StereoLayout::OverUnder => (0..width, view_height..height),
+                };
+
+                [(cam_setup.look_from - offset, (0..view_width, 0..view_height)),
+                 (cam_setup.look_from + offset, right_region)]
+                    .map(|(eye, (x, y))| View::new(cam_setup, (eye, target(eye), cam_setup.vertical_up), x, y, lens))
+                    .into()
+            }
+            Rig::CubeMap => {
+                let (u, v, w) = (centre.u, centre.v, centre.w);
+                let faces = [(u, v), (-u, v), (v, w), (-v, -w), (w, v), (-w, v)];
+                let size = view_height;
+
+                faces.into_iter().enumerate()
+                    .map(|(i, (forward, up))| {
+                        let (x0, y0) = (i as u32 % 3 * size, i as u32 / 3 * size);
+                        let pose = (cam_setup.look_from, cam_setup.look_from + forward, up);
+                        View::new(cam_setup, pose, x0..x0 + size, y0..y0 + size, (PI / 2.0, 1.0, Projection::Perspective))
+                    })
+                    .collect()
+            }
+        };
 
-}
+        let seed = cam_setup.seed.unwrap_or_else(random);
 
-impl Camera {
+        // everything that shapes the camera rays or the light they escape to, for checking
+        // checkpoints against. Debug prints floats exactly, so the text identifies them
+        let optics = format!("{views:?} {} {:?} {} {:?} {:?}",
+            cam_setup.focus_distance, cam_setup.aperture, cam_setup.cats_eye, cam_setup.shutter, cam_setup.sky);
+        let optics = hash(&optics.bytes().map(u64::from).collect::<Vec<_>>());
 
-    pub fn init(cam_setup: &CameraSetup) -> Self {
+        Camera{
 
-        // camera setup
-        let width = (cam_setup.image_height as f32 * cam_setup.aspect_ratio) as u32;
-        let h = cam_setup.vfov.div(2.0).tan();
+            width,
+            height,
+            px_samples: cam_setup.samples_per_px,
+            max_depth: cam_setup.max_depth,
+            film: Film::new(width, height, cam_setup.filter),
+
+            lens_radius: cam_setup.lens_radius(),
+            focus_distance: cam_setup.focus_distance,
+            aperture: cam_setup.aperture.clone(),
+            cats_eye: cam_setup.cats_eye,
+            shutter: cam_setup.shutter,
+            views,
+
+            sky: cam_setup.sky.clone(),
+            sampler: cam_setup.sampler.build(
+                cam_setup.adaptive.map_or(cam_setup.samples_per_px, |a| a.max_spp), seed),
+            exr_precision: cam_setup.exr_precision,
+            // debug renders are already display colours
+            exposure: if cam_setup.debug.is_some() { 0.0 } else { cam_setup.exposure },
+            tone_map: if cam_setup.debug.is_some() { ToneMap::Clamp } else { cam_setup.tone_map },
+            adaptive: cam_setup.adaptive,
+            progressive: cam_setup.progressive.clone(),
+            time_limit: cam_setup.time_limit,
+            crop: cam_setup.crop.map(|(window, output)| {
+                let (x, y) = window.pixel_ranges(width, height);
+                Crop { x, y, output }
+            }),
+            job: cam_setup.job,
+            aovs: cam_setup.aovs.clone(),
+            // the denoiser is guided by the albedo and normal aovs
+            aov_buffer: (!cam_setup.aovs.is_empty() || cam_setup.denoiser.is_some())
+                .then(|| AovBuffer::new(0..width, 0..height)),
+            denoiser: cam_setup.denoiser,
+            debug: cam_setup.debug,
+            observers: cam_setup.observers.clone(),
+            cancel: cam_setup.cancel.clone(),
+
+            seed,
+            sampler_kind: cam_setup.sampler,
+            filter: cam_setup.filter,
+            optics,
+            checkpoint: cam_setup.checkpoint.clone(),
+            passes_done: 0,
 
-        let viewport_height = 2.0 * h * cam_setup.focus_distance;
-        let viewport_width = viewport_height * cam_setup.aspect_ratio;
+        }
 
-        let w = (cam_setup.look_from - cam_setup.look_at).normalized();
-        let u = cam_setup.vertical_up.cross(w).normalized();
-        let v = w.cross(u);
+    }
 
-        let viewport_u = viewport_width  *  u;
-        let viewport_v = viewport_height * -v;
+    pub fn resume(&mut self, path: &Path) -> Result<(), RenderError> {
+        // continues from a checkpoint written by an earlier render with the same settings
 
-        let px_delta_u = viewport_u / (width as f32);
-        let px_delta_v = viewport_v / (cam_setup.image_height as f32);
+        let saved = read_settings(path)?;
+        if saved != self.settings() {
+            return Err(RenderError::InvalidConfig(format!(
+                "checkpoint {} was written with different render settings", path.display())));
+        }
 
-        let viewport_up_left = cam_setup.look_from
-            - (cam_setup.focus_distance * w)
-            - viewport_u / 2.0
-            - viewport_v / 2.0;
-        let px_loc_100 = viewport_up_left + 0.5 * (px_delta_u + px_delta_v);
+        let (_, passes_done) = read_checkpoint(path, &mut self.film)?;
+        self.passes_done = passes_done;
 
-        let defocus_radius = cam_setup.focus_distance
-            * cam_setup.defocus_angle.div(2.0).tan();
+        Ok(())
+    }
 
-        let defocus_disc_u = u * defocus_radius;
-        let defocus_disc_v = v * defocus_radius;
+    pub fn write_partial(&self, path: &Path) -> Result<(), RenderError> {
+        // the film of a render job, for merge_partials. it is a checkpoint, so an
+        // interrupted job can be resumed from it too
+        Ok(write_checkpoint(path, &self.settings(), self.passes_done, &self.film)?)
+    }
 
-        Camera{
+    pub fn merge_partials(&mut self, paths: &[PathBuf]) -> Result<(), RenderError> {
+        // sums the films of the jobs a frame was split into, in place of rendering it
 
-            width,
-            height: cam_setup.image_height,
-            aspect_ratio: cam_setup.aspect_ratio,
-            px_samples: cam_setup.samples_per_px,
-            px_samples_scale: 1.0 / (cam_setup.samples_per_px as f32),
-            max_depth: cam_setup.max_depth,
-            image: RgbImage::new(width, cam_setup.image_height),
+        let settings = self.settings();
+        let mut frame: Option<RenderSettings> = None;
+        let mut jobs = Vec::new();
 
-            // focal_length: cam_setup.,
-            defocus_angle: cam_setup.defocus_angle,
-            viewport_height,
-            viewport_width,
+        for path in paths {
+            // the partial renders have to agree with each other, but with this camera only
+            // on the layout of the film. sample counts and the like are up to the jobs
+            let saved = read_settings(path)?;
+            let layout = |s: &RenderSettings| (s.width, s.height, s.region_x.clone(), s.region_y.clone(), s.filter);
 
-            origin: cam_setup.look_from,
-            viewport_u,
-            viewport_v,
+            if layout(&saved) != layout(&settings) || frame.as_ref().is_some_and(|f| !f.same_frame(&saved)) {
+                return Err(RenderError::InvalidConfig(format!(
+                    "partial render {} is of a different frame", path.display())));
+            }
+            // the jobs have to come from one split of the frame, each at most once, or
+            // some samples would be counted twice
+            let Some(job) = saved.job else {
+                return Err(RenderError::InvalidConfig(format!(
+                    "{} is not a partial render of a job", path.display())));
+            };
+            if jobs.iter().any(|j: &RenderJob| j.index == job.index || j.count != job.count || j.split != job.split) {
+                return Err(RenderError::InvalidConfig(format!(
+                    "partial render {} doesn't fit with the jobs merged before it", path.display())));
+            }
 
-            px_delta_u,
-            px_delta_v,
-            px_loc_100,
-            u, v, w,
+            let mut film = Film::new(self.width, self.height, self.filter);
+            read_checkpoint(path, &mut film)?;
+            self.film.merge(&film);
 
-            defocus_disc_u,
-            defocus_disc_v
+            jobs.push(job);
+            frame.get_or_insert(saved);
+        }
 
+        // a missing job only leaves the frame with fewer samples, so merge anyway
+        if let Some(job) = jobs.first() && jobs.len() < job.count as usize {
+            println!("Warning: merged {} of {} jobs", jobs.len(), job.count);
         }
 
+        Ok(())
     }
 
-    pub fn render(&mut self, world: &HittableList) {
+    fn settings(&self) -> RenderSettings {
+        let (region_x, region_y) = self.render_region();
+
+        RenderSettings {
+            width: self.width,
+            height: self.height,
+            region_x,
+            region_y,
+            seed: self.seed,
+            sampler: self.sampler_kind,
+            filter: self.filter,
+            max_depth: self.max_depth,
+            max_spp: self.total_spp(),
+            pass_spp: self.pass_spp(),
+            adaptive: self.adaptive,
+            job: self.job,
+            optics: self.optics,
+        }
+    }
+
+    pub fn render(&mut self, world: &HittableList) -> Result<RenderSummary, RenderError> {
+        // stops at the first checkpoint or snapshot that can't be written, rather than
+        // carrying on with a render that can't be resumed or watched
+
+        let max_spp = self.max_spp();
+        let pass_spp = self.pass_spp();
+        let passes = max_spp.div_ceil(pass_spp);
 
-        let pg_bar = self.setup_pg_bar(); // setup progress bar
+        // a time-budgeted render keeps adding passes until the deadline (or until every
+        // pixel has max_spp samples), and its progress is measured in time used
+        let deadline = self.time_limit.map(|limit| Instant::now() + limit);
 
-        for (x, y) in iproduct!(
-            (0..self.width).into_iter(),    // x-coord as the outer prod
-            (0..self.height).into_iter())   // y-coord as the inner prod
-            .progress_with(pg_bar) {
+        let tracker = ProgressTracker::new(self.tiles().len() as u64, passes, self.passes_done, self.time_limit);
+        let mut last_snapshot = Instant::now();
 
-            let mut col: Vec3 = (0..self.px_samples).into_par_iter() // for each pixel sample
-                // calc the pixel colour
-                .map(|_| Camera::ray_colour(&self.get_ray(x, y), self.max_depth, world))
-                // then accumulate and scale.
-                .sum::<Vec3>() * self.px_samples_scale;
+        for pass in self.passes_done..passes {
+            if self.stopped(deadline) { break }
 
-            // gamma correction
-            col.apply( |x| if x > 0.0 { x.sqrt() } else { 0.0 } );
-            // clamp
-            col.apply( |x| x.clamp(0.000, 0.999) );
-            // conversion to range 0-255
-            col.apply( |x| (x * 255.0).round() );
+            tracker.start_pass(pass);
+            self.notify(|o| o.on_progress(&tracker.progress()));
 
-            self.image.put_pixel(x, y, Rgb([col.x as u8, col.y as u8, col.z as u8]));
+            let spp_limit = ((pass + 1) * pass_spp).min(max_spp);
+            self.render_pass(world, spp_limit, deadline, &tracker);
+            self.passes_done = pass + 1;
 
+            if let Some(path) = &self.checkpoint {
+                write_checkpoint(path, &self.settings(), self.passes_done, &self.film)
+                    .map_err(|e| io::Error::new(e.kind(), format!("can't write checkpoint {}: {e}", path.display())))?;
+            }
+
+            let preview = self.debug_preview();
+            let (xs, ys) = self.output_region();
+            let frame = FrameBuffer::new(preview.as_ref().unwrap_or(&self.film), xs, ys, self.exposure, self.tone_map);
+            self.notify(|o| o.on_pass(&tracker.progress(), &frame));
+
+            // write the accumulated image so far, except after the last pass
+            if let Some(progressive) = &self.progressive
+                && pass + 1 < passes
+                && progressive.snapshot_due(pass + 1, last_snapshot.elapsed()) {
+                let name = progressive.snapshot_name.clone();
+                // a debug snapshot is saved from the preview, then the raw film put back
+                let raw = preview.map(|film| mem::replace(&mut self.film, film));
+                let mut saved = Ok(());
+                self.suspended(&mut || saved = self.save(Some(&name)));
+                if let Some(raw) = raw { self.film = raw }
+                saved?;
+                last_snapshot = Instant::now();
+            }
         }
 
+        self.denoise();
+        self.finish_debug();
+
+        // the film normalises every pixel by its own filter weights, so pixels cut short
+        // by the deadline are just noisier
+        let (xs, ys) = self.output_region();
+        let counts: Vec<u32> = iproduct!(ys, xs)
+            .map(|(y, x)| self.film.stats(x, y).count())
+            .collect();
+        let total_samples: u64 = counts.iter().map(|&c| c as u64).sum();
+
+        let summary = RenderSummary {
+            cancelled: self.cancelled(),
+            mean_spp: total_samples as f32 / counts.len().max(1) as f32,
+            min_spp: counts.iter().min().copied().unwrap_or(0),
+            max_spp: counts.iter().max().copied().unwrap_or(0),
+            elapsed: tracker.elapsed(),
+        };
+
+        self.notify(|o| o.on_finish(&summary));
+
+        Ok(summary)
+
     }
 
-    fn ray_colour(ray: &Ray, depth: u32, world: &HittableList) -> Vec3 {
+    fn denoise(&mut self) {
+        // filters the output region of the film, each view on its own so they don't
+        // blur into each other
 
-        if depth <= 0 { return Vec3::zero() }
+        let (Some(denoiser), Some(buffer)) = (&self.denoiser, &self.aov_buffer) else { return };
+        let (xs, ys) = self.output_region();
 
-        let rec_option = world.hit(ray, 0.001..f32::INFINITY) ;
+        for view in &self.views {
+            let xs = xs.start.max(view.x.start)..xs.end.min(view.x.end);
+            let ys = ys.start.max(view.y.start)..ys.end.min(view.y.end);
+            if xs.is_empty() || ys.is_empty() { continue }
 
-        if rec_option.is_some() {
-            // calculate the surface normal of the sphere if it's hit
-            let rec = rec_option.unwrap();
+            let radiance = self.film.radiance(xs.clone(), ys.clone());
+            let albedo = buffer.values(Aov::Albedo, xs.clone(), ys.clone());
+            let normal = buffer.values(Aov::Normal, xs.clone(), ys.clone());
+            let variance: Vec<f32> = iproduct!(ys.clone(), xs.clone())
+                .map(|(y, x)| self.film.stats(x, y).mean_variance())
+                .collect();
 
-            let scatter = rec.material.scatter(ray, &rec);
+            let denoised = denoiser.apply(xs.len(), ys.len(), &radiance, &albedo, &normal, &variance);
 
-            return if scatter.is_some() {
-                let (scattered, col) = scatter.unwrap();
-                col * Self::ray_colour(&scattered, depth - 1, world)
-            } else {
-                Vec3::zero()
+            for ((y, x), col) in iproduct!(ys, xs).zip(denoised) {
+                self.film.set_pixel(x, y, col);
             }
+        }
+    }
+
+    fn finish_debug(&mut self) {
+        // the finished debug render in colours, with the scale they're shown at
 
+        let Some(mode) = self.debug else { return };
+        let (xs, ys) = self.output_region();
+        let max = debug_colours(mode, &mut self.film, xs, ys);
+
+        match mode {
+            DebugShading::Distance => self.suspended(&mut || println!("Distance scaled to {max:.2}")),
+            DebugShading::Bounces => self.suspended(&mut || println!("Bounce heatmap scaled to {max:.1} bounces")),
+            DebugShading::Cost => self.suspended(&mut || println!("Cost heatmap scaled to {max:.0} tests")),
+            _ => {}
         }
+    }
 
-        let unit_dir = ray.direction.normalized();
-        let a = 0.5 * (unit_dir.y + 1.0);
+    fn debug_preview(&self) -> Option<Film> {
+        // a debug render as it would finish with the passes so far, for previews and
+        // snapshots, leaving the raw values in the film to keep adding to
 
-        let out = (1.0 - a) * Vec3::one() + a * Vec3::new(0.5, 0.7, 1.0);
+        let mode = self.debug?;
+        let (xs, ys) = self.output_region();
+        let mut film = self.film.clone();
+        debug_colours(mode, &mut film, xs, ys);
+        Some(film)
+    }
 
-        out
+    fn notify(&self, f: impl Fn(&dyn RenderObserver)) {
+        for observer in &self.observers {
+            f(observer.as_ref());
+        }
+    }
+
+    fn suspended(&self, f: &mut dyn FnMut()) {
+        // runs f with every observer's output out of the way
 
+        fn nest(observers: &[Arc<dyn RenderObserver>], f: &mut dyn FnMut()) {
+            match observers.split_first() {
+                Some((first, rest)) => first.suspend(&mut || nest(rest, f)),
+                None => f(),
+            }
+        }
+
+        nest(&self.observers, f);
     }
 
-    fn get_ray(&self, u: u32, v: u32) -> Ray {
-        // generates a ray originating from the camera center directed at a randomly sampled
-        // point centered at pixel i j
+    fn render_pass(
+        &mut self, world: &HittableList, spp_limit: u32, deadline: Option<Instant>, tracker: &ProgressTracker
+    ) {
+        // brings every pixel up to spp_limit samples, or fewer once converged. tiles not
+        // started by the deadline or before the render is cancelled are skipped
 
-        let offset_x = random_range(-0.5..0.5);
-        let offset_y = random_range(-0.5..0.5);
+        let tiles = self.tiles();
 
-        let px_sample = self.px_loc_100
-            + ( (u as f32 + offset_x) * self.px_delta_u)
-            + ( (v as f32 + offset_y) * self.px_delta_v);
+        // tiles are rendered in parallel, each with its own copy of the sampler, and merged
+        // in order afterwards so overlapping filter splats always sum the same way
+        let film_tiles: Vec<Option<(FilmTile, Option<AovBuffer>)>> = tiles.into_par_iter()
+            .map_init(|| self.sampler.clone_box(), |sampler, (xs, ys)| {
+                if self.stopped(deadline) { return None }
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.origin
-        } else { self.defocus_disc_sample() };
-        let ray_direction = (px_sample - ray_origin).normalized();
+                let tile = self.render_tile(xs, ys, spp_limit, world, sampler.as_mut());
 
-        let ray_time = random();
+                tracker.tile_done();
+                self.notify(|o| o.on_progress(&tracker.progress()));
 
-        Ray::new(ray_origin, ray_direction, ray_time)
+                Some(tile)
+            })
+            .collect();
+
+        for (tile, aov_tile) in film_tiles.into_iter().flatten() {
+            self.film.merge_tile(tile);
+            if let (Some(buffer), Some(aov_tile)) = (&mut self.aov_buffer, aov_tile) {
+                buffer.merge_tile(aov_tile);
+            }
+        }
 
     }
 
-    pub fn save(&self, filename: Option<&str>) {
-        // saves the previously rendered image
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|c| c.is_cancelled())
+    }
 
-        let mut out_dir = PathBuf::new();
-        out_dir.push("../RTTNW/output_images");
+    fn stopped(&self, deadline: Option<Instant>) -> bool {
+        // whether no more tiles should be started
+        self.cancelled() || deadline.is_some_and(|d| Instant::now() >= d)
+    }
 
-        if !out_dir.exists() {
-            let res = std::fs::create_dir(&out_dir);
-            res.unwrap_or_else(|e| println!("Error creating output directory: {e}"));
+    fn tiles(&self) -> Vec<(Range<u32>, Range<u32>)> {
+        // the render region split into tiles, row by row within each view
+
+        let (xs, ys) = self.render_region();
+
+        self.views.iter()
+            .flat_map(|view| {
+                let xs = xs.start.max(view.x.start)..xs.end.min(view.x.end);
+                let ys = ys.start.max(view.y.start)..ys.end.min(view.y.end);
+
+                iproduct!(ys.clone().step_by(TILE_SIZE as usize), xs.clone().step_by(TILE_SIZE as usize))
+                    .map(move |(y0, x0)| (x0..(x0 + TILE_SIZE).min(xs.end), y0..(y0 + TILE_SIZE).min(ys.end)))
+            })
+            .enumerate()
+            .filter(|(i, _)| self.job.is_none_or(|job| job.takes_tile(*i)))
+            .map(|(_, tile)| tile)
+            .collect()
+    }
+
+    fn view_at(&self, x: u32, y: u32) -> &View {
+        self.views.iter()
+            .find(|view| view.x.contains(&x) && view.y.contains(&y))
+            .expect("the views cover the frame")
+    }
+
+    fn output_region(&self) -> (Range<u32>, Range<u32>) {
+        // the pixels that end up in the saved image
+        match &self.crop {
+            Some(crop) => (crop.x.clone(), crop.y.clone()),
+            None => (0..self.width, 0..self.height)
         }
+    }
 
-        out_dir = out_dir.join(filename.unwrap_or("output"));
-        out_dir.set_extension("png");
+    fn render_region(&self) -> (Range<u32>, Range<u32>) {
+        // the pixels samples are taken in, by all jobs together. a crop window is widened by the filter's reach
+        // so the pixels along its edges get the same splats as in a full frame render
 
-        self.image.save(&out_dir).unwrap_or_else(|e| println!("Error saving image: {e}"));
+        let (xs, ys) = self.output_region();
+        let pad = if self.crop.is_some() { self.film.padding() } else { 0 };
 
-        println!("Saved rendered image to {}", &out_dir.to_str().unwrap());
+        (xs.start.saturating_sub(pad)..(xs.end + pad).min(self.width),
+         ys.start.saturating_sub(pad)..(ys.end + pad).min(self.height))
+    }
+
+    fn render_tile(
+        &self, xs: Range<u32>, ys: Range<u32>, spp_limit: u32, world: &HittableList, sampler: &mut dyn Sampler
+    ) -> (FilmTile, Option<AovBuffer>) {
+
+        // splats stay inside the tile's view, so views don't bleed into each other
+        let view = self.view_at(xs.start, ys.start);
+        let mut tile = self.film.tile(xs.clone(), ys.clone(), view.x.clone(), view.y.clone());
+        let mut aov_tile = self.aov_buffer.as_ref().map(|_| AovBuffer::new(xs.clone(), ys.clone()));
+        let sample_offset = self.job.map_or(0, |job| job.sample_range(self.total_spp()).start);
+
+        for (y, x) in iproduct!(ys, xs) {
+
+            let mut batch = self.next_batch(tile.stats(x, y), spp_limit);
+
+            while batch > 0 {
+                for _ in 0..batch { // for each pixel sample
+                    let i = sample_offset + tile.stats(x, y).count();
+                    sampler.start_pixel_sample((x, y), i);
+
+                    let (offset_x, offset_y) = sampler.get_2d();
+                    let p_film = (x as f32 + offset_x, y as f32 + offset_y);
+
+                    // calc the sample colour and splat it into the film
+                    // film outside the projection is black, but still sampled like the rest
+                    let ray = self.get_ray(view, p_film, sampler);
+                    let col = match (ray, &mut aov_tile) {
+                        (Some(ray), _) if let Some(mode) = self.debug => self.debug_shade(mode, &ray, world, sampler),
+                        (Some(ray), None) => self.ray_colour(&ray, self.max_depth, world, true, sampler),
+                        (Some(ray), Some(aov_tile)) => {
+                            let (col, aovs) = self.ray_colour_aovs(&ray, view, world, sampler);
+                            aov_tile.add_sample((x, y), &aovs);
+                            col
+                        }
+                        (None, aov_tile) => {
+                            if let Some(aov_tile) = aov_tile { aov_tile.add_sample((x, y), &AovSample::default()) }
+                            Vec3::zero()
+                        }
+                    };
+                    tile.add_sample((x, y), p_film, col);
+                }
+
+                batch = self.next_batch(tile.stats(x, y), spp_limit);
+            }
+        }
+
+        (tile, aov_tile)
+
+    }
+
+    fn total_spp(&self) -> u32 {
+        self.adaptive.map_or(self.px_samples, |a| a.max_spp)
+    }
+
+    fn max_spp(&self) -> u32 {
+        // samples per pixel this process takes, only a share of them for a sample range job
+        let total_spp = self.total_spp();
+        self.job.map_or(total_spp, |job| job.sample_range(total_spp).len() as u32)
+    }
+
+    fn pass_spp(&self) -> u32 {
+        // without progressive rendering the whole frame is a single pass, unless the
+        // render is time-budgeted
+        let max_spp = self.max_spp();
+        let default = if self.time_limit.is_some() { BUDGET_PASS_SPP.min(max_spp) } else { max_spp };
+        self.progressive.as_ref().map_or(default, |p| p.pass_spp.clamp(1, max_spp))
+    }
+
+    fn next_batch(&self, stats: PixelStats, spp_limit: u32) -> u32 {
+        // how many more samples a pixel gets before it is checked again
+
+        let count = stats.count();
+        let limit = spp_limit.min(self.max_spp());
+
+        if count >= limit { return 0 }
+
+        let Some(adaptive) = self.adaptive else { return limit - count };
+
+        if count < adaptive.min_spp {
+            adaptive.min_spp.min(limit) - count
+        } else if stats.relative_error() < adaptive.threshold {
+            0
+        } else {
+            adaptive.min_spp.max(1).min(limit - count)
+        }
 
     }
 
-    fn setup_pg_bar(&self) -> ProgressBar {
-        // set up the progress bar...
+    fn ray_colour(
+        &self, ray: &Ray, depth: u32, world: &HittableList, include_sun: bool, sampler: &mut dyn Sampler
+    ) -> Vec3 {
+        // include_sun is false after a diffuse bounce, where the sun has already been
+        // accounted for by sampling it directly
 
-        let pg_bar = ProgressBar::new(self.height as u64 * self.width as u64);
-        pg_bar.set_style(
-            ProgressStyle::with_template("elapsed: [{elapsed}] {bar:50.cyan/blue} {percent:.bold.cyan/blue}% {msg}")
-                .unwrap()
-                .progress_chars("##-"));
+        if depth == 0 { return Vec3::zero() }
 
-        pg_bar.set_message("rendering frame...");
+        let Some(rec) = world.hit(ray, 0.001..f32::INFINITY) else {
+            return self.sky.radiance(ray.direction, include_sun);
+        };
 
-        pg_bar
+        self.shade(ray, &rec, depth, world, sampler)
 
     }
 
-    fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
+    fn shade(&self, ray: &Ray, rec: &HitRecord, depth: u32, world: &HittableList, sampler: &mut dyn Sampler) -> Vec3 {
+        // light leaving the surface hit at rec back along ray
 
-        let rand_vec = random_unit_vec();
-        if normal.dot(rand_vec) > 0.0 { rand_vec } else { -rand_vec }
+        match self.bounce(ray, rec, world, sampler) {
+            (direct, Some((scattered, col, include_sun))) => direct
+                + col * self.ray_colour(&scattered, depth - 1, world, include_sun, sampler),
+            (direct, None) => direct
+        }
 
     }
 
-    fn defocus_disc_sample(&self) -> Vec3 {
-        let offset = random_in_unit_disk();
-        self.origin + ( offset.x * self.defocus_disc_u ) + ( offset.y * self.defocus_disc_v )
+    fn bounce(
+        &self, ray: &Ray, rec: &HitRecord, world: &HittableList, sampler: &mut dyn Sampler
+    ) -> (Vec3, Option<(Ray, Vec3, bool)>) {
+        // the light a surface reflects straight from the sun, and the ray it scatters with
+        // its colour and whether the sun is still to be found along it
+
+        let albedo = rec.material.diffuse_albedo();
+
+        // direct lighting from the sun disc on diffuse surfaces
+        let direct = match (albedo, self.sky.sample_sun(sampler.get_2d())) {
+            (Some(albedo), Some(sun)) => {
+                let cos_theta = rec.normal.dot(sun.direction);
+                let shadow_ray = Ray::new(rec.point, sun.direction, ray.time);
+
+                if cos_theta > 0.0 && world.hit(&shadow_ray, 0.001..f32::INFINITY).is_none() {
+                    albedo * sun.radiance * (cos_theta * sun.solid_angle / PI)
+                } else {
+                    Vec3::zero()
+                }
+            }
+            _ => Vec3::zero()
+        };
+
+        let scattered = rec.material.scatter(ray, rec, sampler)
+            .map(|(scattered, col)| (scattered, col, albedo.is_none()));
+
+        (direct, scattered)
+
     }
 
+    fn ray_colour_aovs(
+        &self, ray: &Ray, view: &View, world: &HittableList, sampler: &mut dyn Sampler
+    ) -> (Vec3, AovSample) {
+        // ray_colour for a camera ray, and the aovs of the sample. the colour takes the
+        // same random numbers in the same order, so the beauty image comes out the same
+        // with or without aovs
+
+        if self.max_depth == 0 { return (Vec3::zero(), AovSample::default()) }
+
+        let Some(rec) = world.hit(ray, 0.001..f32::INFINITY) else {
+            // the sky's albedo is its colour, but the sun is far too bright to guide anything
+            let sky = self.sky.radiance(ray.direction, true);
+            let albedo = sky.clamped(Vec3::zero(), Vec3::one());
+            return (sky, AovSample { albedo, direct: sky, ..AovSample::default() });
+        };
+
+        // light from the sky straight after the first bounce is direct, anything off a
+        // second surface indirect
+        let (sun, scattered) = self.bounce(ray, &rec, world, sampler);
+        let (direct, indirect) = match scattered {
+            Some((scattered, col, include_sun)) if self.max_depth > 1 => {
+                match world.hit(&scattered, 0.001..f32::INFINITY) {
+                    None => (sun + col * self.sky.radiance(scattered.direction, include_sun), Vec3::zero()),
+                    Some(next) => (sun, col * self.shade(&scattered, &next, self.max_depth - 1, world, sampler)),
+                }
+            }
+            _ => (sun, Vec3::zero())
+        };
+
+        // depth along the view axis where it's the same for the whole view, otherwise
+        // along the ray
+        let depth = match view.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => (rec.point - ray.origin).dot(-view.w),
+            Projection::Fisheye { .. } | Projection::Equirectangular => (rec.point - ray.origin).mag(),
+        };
+
+        let aovs = AovSample {
+            albedo: rec.material.albedo(),
+            normal: rec.normal.normalized(),
+            position: rec.point,
+            depth,
+            material: rec.material_id,
+            object: rec.object_id,
+            direct,
+            indirect,
+        };
+
+        (direct + indirect, aovs)
+
+    }
+
+    fn debug_shade(&self, mode: DebugShading, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler) -> Vec3 {
+        // what a debug render shows for a camera ray. distances and counts are left raw
+        // for finish_debug to colour
+
+        let hit = || world.hit(ray, 0.001..f32::INFINITY);
+
+        match mode {
+            DebugShading::Normal => hit().map_or(Vec3::zero(), |rec| rec.normal * 0.5 + Vec3::broadcast(0.5)),
+            DebugShading::Uv => hit().map_or(Vec3::zero(), |rec| { let (u, v) = rec.uv(); Vec3::new(u, v, 0.0) }),
+            DebugShading::Distance => hit().map_or(Vec3::zero(), |rec| Vec3::broadcast((rec.point - ray.origin).mag())),
+            DebugShading::FrontFace => hit().map_or(Vec3::zero(), |rec| front_face_colour(rec.front_face)),
+            DebugShading::Material => hit().map_or(Vec3::zero(), |rec| material_colour(rec.material.kind())),
+            DebugShading::Bounces => Vec3::broadcast(self.bounces(ray, world, sampler) as f32),
+            DebugShading::Cost => Vec3::broadcast(world.hit_cost(ray, 0.001..f32::INFINITY) as f32),
+        }
+
+    }
+
+    fn bounces(&self, ray: &Ray, world: &HittableList, sampler: &mut dyn Sampler) -> u32 {
+        // how many surfaces the path from ray hits, as ray_colour would follow it
+
+        let mut ray = Ray::new(ray.origin, ray.direction, ray.time);
+
+        for bounce in 0..self.max_depth {
+            let Some(rec) = world.hit(&ray, 0.001..f32::INFINITY) else { return bounce };
+            match rec.material.scatter(&ray, &rec, sampler) {
+                Some((scattered, _)) => ray = scattered,
+                None => return bounce + 1,
+            }
+        }
+
+        self.max_depth
+    }
+
+    fn get_ray(&self, view: &View, p_film: (f32, f32), sampler: &mut dyn Sampler) -> Option<Ray> {
+        // generates a ray of view through the film position p_film, given in continuous pixel
+        // coordinates. None where the projection doesn't cover the film (outside a fisheye's
+        // image circle)
+
+        // always drawn so the later dimensions line up with and without defocus
+        let lens_sample = sampler.get_2d();
+        let ray_time = self.shutter.sample(sampler.get_1d());
+
+        // film position within the view, and relative to its centre, in pixels
+        let (width, height) = (view.x.len() as f32, view.y.len() as f32);
+        let p_film = (p_film.0 - view.x.start as f32, p_film.1 - view.y.start as f32);
+        let (dx, dy) = (p_film.0 - width / 2.0, p_film.1 - height / 2.0);
+
+        // the point on the lens, unless the lens is a pinhole
+        let lens_point = match self.lens_radius > 0.0 {
+            true => Some(self.lens_point(lens_sample, (dx, dy), (width, height))?),
+            false => None,
+        };
+
+        let (centre, direction) = match view.projection {
+            Projection::Perspective => {
+                let px_sample = view.px_loc_100
+                    + ( (p_film.0 - 0.5) * view.px_delta_u)
+                    + ( (p_film.1 - 0.5) * view.px_delta_v);
+
+                let ray_origin = match lens_point {
+                    None => view.origin,
+                    Some(p) => view.defocus_disc_sample(p),
+                };
+                let ray_direction = (px_sample - ray_origin).normalized();
+
+                return Some(Ray::new(ray_origin, ray_direction, ray_time));
+            }
+            Projection::Orthographic { view_height } => {
+                let scale = view_height / height;
+                (view.origin + (dx * scale) * view.u - (dy * scale) * view.v, -view.w)
+            }
+            Projection::Fisheye { fov } => {
+                // equidistant: the angle off the view axis grows linearly out to the edge of
+                // the image circle, which fits the shorter side of the view
+                let radius = width.min(height) / 2.0;
+                let r = (dx * dx + dy * dy).sqrt() / radius;
+                if r > 1.0 { return None }
+
+                let theta = r * fov / 2.0;
+                let phi = dy.atan2(dx);
+                let direction = theta.sin() * phi.cos() * view.u
+                    - theta.sin() * phi.sin() * view.v
+                    - theta.cos() * view.w;
+                (view.origin, direction)
+            }
+            Projection::Equirectangular => {
+                // longitude across the width from -180 to 180 degrees, latitude up the height
+                // from -90 to 90, with look_at in the centre
+                let longitude = dx / width * 2.0 * PI;
+                let latitude = -dy / height * PI;
+                let direction = latitude.cos() * longitude.sin() * view.u
+                    + latitude.sin() * view.v
+                    - latitude.cos() * longitude.cos() * view.w;
+                (view.origin, direction)
+            }
+        };
+
+        let Some(lens_point) = lens_point else {
+            return Some(Ray::new(centre, direction, ray_time));
+        };
+
+        // the same thin lens, moved to the centre of projection, focused on the point
+        // focus_distance along the ray
+        let focus = centre + direction * self.focus_distance;
+        let ray_origin = centre + (view.defocus_disc_sample(lens_point) - view.origin);
+
+        Some(Ray::new(ray_origin, (focus - ray_origin).normalized(), ray_time))
+
+    }
+
+    fn lens_point(&self, u: (f32, f32), (dx, dy): (f32, f32), (width, height): (f32, f32)) -> Option<Vec3> {
+        // a point on the aperture, in units of the lens radius. with cat's eye vignetting,
+        // the lens barrel cuts off the side of the aperture away from the image centre for
+        // film positions (dx, dy) off it, and rays through the cut off part are blocked
+
+        let p = self.aperture.sample(u);
+        if self.cats_eye <= 0.0 { return Some(p) }
+
+        let half_diagonal = (width * width + height * height).sqrt() / 2.0;
+        let barrel = Vec3::new(-dx, dy, 0.0) * (self.cats_eye / half_diagonal);
+
+        ((p - barrel).mag_sq() <= 1.0).then_some(p)
+    }
+
+    pub fn save(&self, filename: Option<&str>) -> Result<(), RenderError> {
+        // saves the previously rendered image in the output directory
+        self.save_to(&Self::output_path(filename.unwrap_or("output"))?)
+    }
+
+    pub fn save_to(&self, out_dir: &Path) -> Result<(), RenderError> {
+        // saves the previously rendered image to the path out_dir
+
+        // the format follows the file extension. exr, hdr and pfm keep the linear
+        // radiance, everything else is quantised to 8 bits by the image crate
+        let extension = out_dir.extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png")
+            .to_ascii_lowercase();
+
+        // aovs go in an exr as layers alongside the image, and next to anything else as
+        // images of their own
+        let aovs = self.aov_values();
+
+        if let "exr" | "hdr" | "pfm" = extension.as_str() {
+            let (width, height, pixels) = self.output_radiance(out_dir)?;
+            match extension.as_str() {
+                "exr" => {
+                    let layers: Vec<(&str, &[Vec3])> = std::iter::once(("", pixels.as_slice()))
+                        .chain(aovs.iter().map(|(aov, values)| (aov.name(), values.as_slice())))
+                        .collect();
+                    write_exr(out_dir, width, height, &layers, self.exr_precision)?
+                }
+                "hdr" => write_hdr(out_dir, width, height, &pixels)?,
+                _ => write_pfm(out_dir, width, height, &pixels)?,
+            }
+        } else {
+            self.output_image(out_dir)?.save(out_dir)?;
+        }
+
+        println!("Saved rendered image to {}", out_dir.display());
+
+        if extension != "exr" {
+            let (xs, ys) = self.output_region();
+            let (width, height) = (xs.len() as u32, ys.len() as u32);
+
+            for (aov, values) in &aovs {
+                let path = Self::aov_path(out_dir, *aov);
+                match extension.as_str() {
+                    "hdr" => write_hdr(&path, width, height, values)?,
+                    "pfm" => write_pfm(&path, width, height, values)?,
+                    _ => aov_image(*aov, width, height, values, self.exposure, self.tone_map).save(&path)?,
+                }
+                println!("Saved {} aov to {}", aov.name(), path.display());
+            }
+        }
+
+        Ok(())
+
+    }
+
+    fn aov_values(&self) -> Vec<(Aov, Vec<Vec3>)> {
+        // the output region of each aov asked for
+        let (xs, ys) = self.output_region();
+        let Some(buffer) = &self.aov_buffer else { return Vec::new() };
+
+        self.aovs.iter()
+            .map(|&aov| (aov, buffer.values(aov, xs.clone(), ys.clone())))
+            .collect()
+    }
+
+    fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+        // path with the aov's name added to the file name, as name_albedo.png
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+        let name = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => format!("{stem}_{}.{extension}", aov.name()),
+            None => format!("{stem}_{}", aov.name()),
+        };
+        path.with_file_name(name)
+    }
+
+    pub fn image(&self) -> RgbImage {
+        // the rendered output region, tone mapped to 8 bits
+        let (xs, ys) = self.output_region();
+        self.film.to_image(xs, ys, self.exposure, self.tone_map)
+    }
+
+    fn output_radiance(&self, path: &Path) -> Result<(u32, u32, Vec<Vec3>), RenderError> {
+        // the linear pixels to write to path: the output region, or for a patching crop the
+        // image already at path (black if there is none) with the region replaced
+
+        let (xs, ys) = self.output_region();
+        let Some(Crop { output: CropOutput::Patch, .. }) = self.crop else {
+            return Ok((xs.len() as u32, ys.len() as u32, self.film.radiance(xs, ys)));
+        };
+
+        let mut pixels = if !path.exists() {
+            vec![Vec3::zero(); (self.width * self.height) as usize]
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("pfm")) {
+            let (width, height, pixels) = read_pfm(path)?;
+            self.check_patch_size(path, width, height)?;
+            pixels
+        } else {
+            let image = image::open(path)?.to_rgb32f();
+            self.check_patch_size(path, image.width(), image.height())?;
+            image.pixels().map(|p| Vec3::new(p[0], p[1], p[2])).collect()
+        };
+
+        for (y, x) in iproduct!(ys, xs) {
+            pixels[(y * self.width + x) as usize] = self.film.pixel(x, y);
+        }
+
+        Ok((self.width, self.height, pixels))
+    }
+
+    fn output_image(&self, path: &Path) -> Result<RgbImage, RenderError> {
+        // 8 bit counterpart of output_radiance
+
+        let (xs, ys) = self.output_region();
+        let region = self.film.to_image(xs.clone(), ys.clone(), self.exposure, self.tone_map);
+        let Some(Crop { output: CropOutput::Patch, .. }) = self.crop else { return Ok(region) };
+
+        let mut image = if path.exists() {
+            let image = image::open(path)?.to_rgb8();
+            self.check_patch_size(path, image.width(), image.height())?;
+            image
+        } else {
+            RgbImage::new(self.width, self.height)
+        };
+
+        image::imageops::replace(&mut image, &region, xs.start as i64, ys.start as i64);
+
+        Ok(image)
+    }
+
+    fn check_patch_size(&self, path: &Path, width: u32, height: u32) -> Result<(), RenderError> {
+        if (width, height) == (self.width, self.height) { return Ok(()) }
+
+        Err(RenderError::InvalidConfig(format!("can't patch {}: it is {width}x{height}, the frame is {}x{}",
+            path.display(), self.width, self.height)))
+    }
+
+    pub fn save_sample_heatmap(&self, filename: &str) -> Result<(), RenderError> {
+        // saves the number of samples each pixel in the output region received, scaled to
+        // the most sampled pixel
+
+        let out_dir = Self::output_path(filename)?;
+        let (xs, ys) = self.output_region();
+
+        let counts = self.sample_counts();
+        let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+
+        let heatmap = RgbImage::from_fn(xs.len() as u32, ys.len() as u32, |x, y| {
+            let t = counts[(y * xs.len() as u32 + x) as usize] as f32 / max_count as f32;
+            let col = heatmap_colour(t) * 255.0;
+            Rgb([col.x.round() as u8, col.y.round() as u8, col.z.round() as u8])
+        });
+
+        heatmap.save(&out_dir)?;
+
+        println!("Saved sample heatmap to {} (max {max_count} samples)", out_dir.display());
+        Ok(())
+
+    }
+
+    fn sample_counts(&self) -> Vec<u32> {
+        // the number of samples each pixel in the output region received, row-major
+        let (xs, ys) = self.output_region();
+
+        iproduct!(ys, xs)
+            .map(|(y, x)| self.film.stats(x, y).count())
+            .collect()
+    }
+
+    pub(crate) fn output_path(filename: &str) -> Result<PathBuf, RenderError> {
+        // resolves filename inside the output directory, defaulting to png
+
+        let mut out_dir = PathBuf::new();
+        out_dir.push("../RTTNW/output_images");
+
+        if !out_dir.exists() {
+            std::fs::create_dir_all(&out_dir)?;
+        }
+
+        out_dir = out_dir.join(filename);
+        if out_dir.extension().is_none() { out_dir.set_extension("png"); }
+
+        Ok(out_dir)
+
+    }
+
+}
+
+fn debug_colours(mode: DebugShading, film: &mut Film, xs: Range<u32>, ys: Range<u32>) -> f32 {
+    // turns the raw distances, bounce counts and test counts of a debug render in xs * ys
+    // into colours, scaled to the largest there (counts on a log scale, so a few long paths
+    // don't leave the rest black), and every colour into the linear value that displays as
+    // it. returns the largest
+
+    let max = iproduct!(ys.clone(), xs.clone())
+        .map(|(y, x)| film.pixel(x, y).x)
+        .fold(0.0, f32::max)
+        .max(1e-6);
+
+    for (y, x) in iproduct!(ys, xs) {
+        let v = film.pixel(x, y);
+        let mut col = match mode {
+            DebugShading::Distance => Vec3::broadcast(v.x / max),
+            DebugShading::Bounces | DebugShading::Cost =>
+                heatmap_colour((v.x.max(0.0).ln_1p() / max.ln_1p()).clamp(0.0, 1.0)),
+            _ => v,
+        };
+        col.apply(|c| srgb_decode(c.clamp(0.0, 1.0)));
+        film.set_pixel(x, y, col);
+    }
+
+    max
+}
+
+fn heatmap_colour(t: f32) -> Vec3 {
+    // black -> purple -> red -> orange -> yellow ramp for t in 0..1
+
+    let stops = [
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.35, 0.05, 0.5),
+        Vec3::new(0.85, 0.15, 0.25),
+        Vec3::new(1.0, 0.55, 0.0),
+        Vec3::new(1.0, 1.0, 0.6),
+    ];
+
+    let x = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (x.floor() as usize).min(stops.len() - 2);
+    let f = x - i as f32;
+
+    stops[i] * (1.0 - f) + stops[i + 1] * f
 }
 
-pub fn random_unit_vec() -> Vec3 {
+pub fn random_unit_vec(rng: &mut impl Rng) -> Vec3 {
 
     loop {
         let rand_vec = Vec3::new(
-            random_range(-1.0..1.0) as f32,
-            random_range(-1.0..1.0) as f32,
-            random_range(-1.0..1.0) as f32);
+            rng.random_range(-1.0..1.0) as f32,
+            rng.random_range(-1.0..1.0) as f32,
+            rng.random_range(-1.0..1.0) as f32);
 
         let len_sq = rand_vec.mag_sq();
 
@@ -250,60 +1067,959 @@ pub fn random_unit_vec() -> Vec3 {
 
 }
 
+// render the frame in passes of pass_spp samples per pixel, saving the image accumulated
+// so far to snapshot_name every snapshot_passes passes and/or every snapshot_interval
+#[derive(Clone)]
+pub struct ProgressiveRendering {
+    pub pass_spp: u32,
+    pub snapshot_passes: Option<u32>,
+    pub snapshot_interval: Option<Duration>,
+    pub snapshot_name: String,
+}
+
+impl ProgressiveRendering {
+
+    fn snapshot_due(&self, passes_done: u32, since_last: Duration) -> bool {
+        self.snapshot_passes.is_some_and(|k| k > 0 && passes_done.is_multiple_of(k))
+            || self.snapshot_interval.is_some_and(|t| since_last >= t)
+    }
+
+}
+
+// how the camera maps the film onto ray directions. all of them look from look_from
+// towards look_at, with vertical_up at the top of the frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    // thin lens perspective, with the vertical field of view of the CameraSetup
+    Perspective,
+    // parallel rays, over a view view_height world units high
+    Orthographic { view_height: f32 },
+    // equidistant fisheye, fov (radians) across the image circle
+    Fisheye { fov: f32 },
+    // the full sphere of directions as a latitude-longitude panorama, best at 2:1
+    Equirectangular,
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "name" or "name:value", with the view height of orthographic (default 4) and the
+        // field of view in degrees of fisheye (default 180)
+
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => {
+                let value: f32 = value.trim().parse().map_err(|_| format!("bad value in projection '{s}'"))?;
+                (name, Some(value))
+            }
+            None => (s, None),
+        };
+
+        match (name.trim().to_ascii_lowercase().as_str(), value) {
+            ("perspective", None) => Ok(Projection::Perspective),
+            ("orthographic", v) => Ok(Projection::Orthographic { view_height: v.unwrap_or(4.0) }),
+            ("fisheye", v) => Ok(Projection::Fisheye { fov: v.unwrap_or(180.0).to_radians() }),
+            ("equirectangular" | "latlong", None) => Ok(Projection::Equirectangular),
+            _ => Err(format!("unknown projection '{s}'"))
+        }
+    }
+}
+
+// a sub-rectangle of the frame, from (x0, y0) inclusive to (x1, y1) exclusive, either in
+// pixels or as fractions of the frame size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropWindow {
+    Pixels { x0: u32, y0: u32, x1: u32, y1: u32 },
+    Normalised { x0: f32, y0: f32, x1: f32, y1: f32 },
+}
+
+impl CropWindow {
+
+    fn pixel_ranges(&self, width: u32, height: u32) -> (Range<u32>, Range<u32>) {
+        // clamped to the frame. normalised windows are rounded outwards to whole pixels
+
+        let (x0, y0, x1, y1) = match *self {
+            CropWindow::Pixels { x0, y0, x1, y1 } => (x0, y0, x1, y1),
+            CropWindow::Normalised { x0, y0, x1, y1 } => (
+                (x0 * width as f32).floor() as u32,
+                (y0 * height as f32).floor() as u32,
+                (x1 * width as f32).ceil() as u32,
+                (y1 * height as f32).ceil() as u32,
+            ),
+        };
+
+        let (x1, y1) = (x1.min(width), y1.min(height));
+        (x0.min(x1)..x1, y0.min(y1)..y1)
+    }
+
+}
+
+impl FromStr for CropWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "x0,y0,x1,y1" as fractions of the frame ("0.25,0.1,0.5,0.4"), or in pixels with
+        // a px suffix ("64,32,192,160px")
+
+        let (values, pixels) = match s.trim().strip_suffix("px") {
+            Some(values) => (values, true),
+            None => (s, false),
+        };
+        let values: Vec<&str> = values.split(',').map(str::trim).collect();
+        let [x0, y0, x1, y1] = values[..] else {
+            return Err(format!("crop window '{s}' should be x0,y0,x1,y1 or x0,y0,x1,y1px"));
+        };
+
+        let window = if pixels {
+            let [x0, y0, x1, y1] = [x0, y0, x1, y1].map(|v| v.parse::<u32>());
+            let (Ok(x0), Ok(y0), Ok(x1), Ok(y1)) = (x0, y0, x1, y1) else {
+                return Err(format!("crop window '{s}' should be in whole pixels"));
+            };
+            if x0 >= x1 || y0 >= y1 { return Err(format!("crop window '{s}' is empty")) }
+
+            CropWindow::Pixels { x0, y0, x1, y1 }
+        } else {
+            let [x0, y0, x1, y1] = [x0, y0, x1, y1].map(|v| v.parse::<f32>().unwrap_or(f32::NAN));
+            if ![x0, y0, x1, y1].iter().all(|v| (0.0..=1.0).contains(v)) {
+                return Err(format!("crop window '{s}' should be fractions in 0..1, or pixels with a px suffix"));
+            }
+            if x0 >= x1 || y0 >= y1 { return Err(format!("crop window '{s}' is empty")) }
+
+            CropWindow::Normalised { x0, y0, x1, y1 }
+        };
+
+        Ok(window)
+    }
+}
+
+// whether a cropped render is saved as just the crop window, or patched into the
+// full frame image already at the output path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropOutput {
+    Cropped,
+    Patch,
+}
+
+impl FromStr for CropOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cropped" => Ok(CropOutput::Cropped),
+            "patch" => Ok(CropOutput::Patch),
+            _ => Err(format!("unknown crop output '{s}'"))
+        }
+    }
+}
+
+// one of the views of the camera's rig, covering x * y of the film
+#[derive(Debug)]
+struct View {
+    x: Range<u32>,
+    y: Range<u32>,
+    projection: Projection,
+
+    origin: Vec3,
+    u: Vec3, v: Vec3, w: Vec3,
+
+    px_delta_u: Vec3,
+    px_delta_v: Vec3,
+    px_loc_100: Vec3,
+
+    defocus_disc_u: Vec3,
+    defocus_disc_v: Vec3,
+}
+
+impl View {
+
+    fn new(
+        cam_setup: &CameraSetup,
+        (look_from, look_at, vertical_up): (Vec3, Vec3, Vec3),
+        x: Range<u32>,
+        y: Range<u32>,
+        (vfov, aspect_ratio, projection): (f32, f32, Projection),
+    ) -> Self {
+        // the view's basis and viewport, with the lens of cam_setup
+
+        let h = vfov.div(2.0).tan();
+
+        let viewport_height = 2.0 * h * cam_setup.focus_distance;
+        let viewport_width = viewport_height * aspect_ratio;
+
+        let w = (look_from - look_at).normalized();
+        let u = vertical_up.cross(w).normalized();
+        let v = w.cross(u);
+
+        let viewport_u = viewport_width  *  u;
+        let viewport_v = viewport_height * -v;
+
+        let px_delta_u = viewport_u / (x.len() as f32);
+        let px_delta_v = viewport_v / (y.len() as f32);
+
+        let viewport_up_left = look_from
+            - (cam_setup.focus_distance * w)
+            - viewport_u / 2.0
+            - viewport_v / 2.0;
+        let px_loc_100 = viewport_up_left + 0.5 * (px_delta_u + px_delta_v);
+
+        let defocus_radius = cam_setup.lens_radius();
+
+        View {
+            x, y,
+            projection,
+            origin: look_from,
+            u, v, w,
+            px_delta_u,
+            px_delta_v,
+            px_loc_100,
+            defocus_disc_u: u * defocus_radius,
+            defocus_disc_v: v * defocus_radius,
+        }
+    }
+
+    fn defocus_disc_sample(&self, offset: Vec3) -> Vec3 {
+        // the point offset (in units of the lens radius) from the centre of the lens
+        self.origin + ( offset.x * self.defocus_disc_u ) + ( offset.y * self.defocus_disc_v )
+    }
+
+}
+
+#[derive(Clone)]
+struct Crop {
+    x: Range<u32>,
+    y: Range<u32>,
+    output: CropOutput,
+}
+
+#[derive(Clone)]
 pub struct CameraSetup {
     image_height: u32,
     aspect_ratio: f32,
+    width: Option<u32>,
     samples_per_px: u32,
     max_depth: u32,
-    // focal_length: f32,
     vfov: f32, // in radians
+    lens: Option<Lens>,
     look_from: Vec3,
     look_at: Vec3,
     vertical_up: Vec3,
     defocus_angle: f32,
-    focus_distance: f32
+    focus_distance: f32,
+    aperture: ApertureShape,
+    cats_eye: f32,
+    shutter: Shutter,
+    projection: Projection,
+    rig: Rig,
+    sky: Sky,
+    sampler: SamplerKind,
+    filter: Filter,
+    exr_precision: ExrPrecision,
+    exposure: f32, // in stops
+    tone_map: ToneMap,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<ProgressiveRendering>,
+    time_limit: Option<Duration>,
+    crop: Option<(CropWindow, CropOutput)>,
+    job: Option<RenderJob>,
+    aovs: Vec<Aov>,
+    denoiser: Option<Denoiser>,
+    debug: Option<DebugShading>,
+    observers: Vec<Arc<dyn RenderObserver>>,
+    cancel: Option<CancelToken>,
+    seed: Option<u64>,
+    checkpoint: Option<PathBuf>,
 }
 
 impl CameraSetup {
-    // helper struct for initializing camera objects
-    pub fn new(
-        image_height: u32,
-        aspect_ratio: f32,
-        samples_per_px: u32,
-        max_depth: u32,
-        vertical_field_of_view: f32,
-        look_from: Vec3,
-        look_at: Vec3,
-        vertical_up: Vec3,
-        defocus_angle: f32,
-        focus_distance: f32
-    ) -> Self {
-        CameraSetup {
-            image_height,
-            aspect_ratio,
-            samples_per_px,
-            max_depth,
-            vfov: vertical_field_of_view,
-            look_from,
-            look_at,
-            vertical_up,
-            defocus_angle,
-            focus_distance
+    // builder for camera objects: start from default(), change what's needed with the
+    // with_* setters and finish with build(). angles are in radians
+
+    pub fn with_image_height(mut self, image_height: u32) -> Self {
+        self.image_height = image_height;
+        self
+    }
+
+    pub fn with_aspect_ratio(mut self, aspect_ratio: f32) -> Self {
+        // the image width is image_height * aspect_ratio, unless set with with_width
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn with_width(mut self, width: u32) -> Self {
+        // a fixed image width, which overrides the aspect ratio
+        self.width = Some(width);
+        self
+    }
+
+    pub fn with_samples_per_px(mut self, samples_per_px: u32) -> Self {
+        self.samples_per_px = samples_per_px;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        // the most bounces a ray path takes
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_vfov(mut self, vfov: f32) -> Self {
+        // vertical field of view
+        self.vfov = vfov;
+        self
+    }
+
+    pub fn with_look_from(mut self, look_from: Vec3) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn with_look_at(mut self, look_at: Vec3) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn with_vertical_up(mut self, vertical_up: Vec3) -> Self {
+        self.vertical_up = vertical_up;
+        self
+    }
+
+    pub fn with_defocus_angle(mut self, defocus_angle: f32) -> Self {
+        // cone angle of the rays through each pixel, 0 for a pinhole camera
+        self.defocus_angle = defocus_angle;
+        self
+    }
+
+    pub fn with_focus_distance(mut self, focus_distance: f32) -> Self {
+        // distance from look_from to the plane in perfect focus
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    pub fn with_camera_key(self, key: &CameraKey) -> Self {
+        // where a camera path has the camera at one time
+        self.with_look_from(key.look_from)
+            .with_look_at(key.look_at)
+            .with_vfov(key.vfov)
+            .with_focus_distance(key.focus_distance)
+    }
+
+    pub fn with_lens(mut self, focal_length: f32, f_number: f32) -> Self {
+        // a photographic lens, focal_length in mm on a full frame sensor. it sets the field
+        // of view and the aperture in place of vfov and the defocus angle
+        self.lens = Some(Lens { focal_length, f_number });
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: ApertureShape) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn with_cats_eye(mut self, strength: f32) -> Self {
+        // how far the lens barrel cuts into the aperture towards the corners of the frame,
+        // as a fraction of the aperture radius. 0 turns it off
+        self.cats_eye = strength;
+        self
+    }
+
+    pub fn with_shutter(mut self, shutter: Shutter) -> Self {
+        // rays are sent at times between the shutter opening and closing, for motion blur
+        self.shutter = shutter;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        // vfov only applies to the perspective projection
+        self.projection = projection;
+        self
+    }
+
+    pub fn with_rig(mut self, rig: Rig) -> Self {
+        // a stereo pair or cube map instead of a single view, in one frame
+        self.rig = rig;
+        self
+    }
+
+    pub fn with_sky(mut self, sky: Sky) -> Self {
+        self.sky = sky;
+        self
+    }
+
+    pub fn with_sampler(mut self, sampler: SamplerKind) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_exr_precision(mut self, precision: ExrPrecision) -> Self {
+        self.exr_precision = precision;
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+        self.tone_map = tone_map;
+        self
+    }
+
+    pub fn with_adaptive_sampling(mut self, min_spp: u32, max_spp: u32, threshold: f32) -> Self {
+        // samples_per_px is ignored while adaptive sampling is on
+        self.adaptive = Some(AdaptiveSampling {
+            min_spp: min_spp.max(1),
+            max_spp: max_spp.max(min_spp),
+            threshold,
+        });
+        self
+    }
+
+    pub fn with_progressive(mut self, progressive: ProgressiveRendering) -> Self {
+        self.progressive = Some(progressive);
+        self
+    }
+
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        // render passes until the time runs out, samples_per_px becoming an upper bound
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    pub fn with_crop(mut self, window: CropWindow, output: CropOutput) -> Self {
+        // only render the pixels inside window
+        self.crop = Some((window, output));
+        self
+    }
+
+    pub fn with_job(mut self, job: RenderJob) -> Self {
+        // only render this job's part of the frame
+        self.job = Some(job);
+        self
+    }
+
+    pub fn with_aovs(mut self, aovs: Vec<Aov>) -> Self {
+        // also save these aovs of the first hits. they aren't kept in checkpoints or
+        // partial renders
+        self.aovs = aovs;
+        self
+    }
+
+    pub fn with_denoiser(mut self, denoiser: Denoiser) -> Self {
+        // filters the noise out of the finished render
+        self.denoiser = Some(denoiser);
+        self
+    }
+
+    pub fn with_debug_shading(mut self, mode: DebugShading) -> Self {
+        // shows mode instead of path tracing the scene, with no exposure or tone mapping
+        self.debug = Some(mode);
+        self
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn RenderObserver>) -> Self {
+        // reports the render's progress to observer, on top of any added before
+        self.observers.push(observer);
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel: CancelToken) -> Self {
+        // cancelling the token stops the render after the tiles in flight
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // fixes the sample pattern, otherwise a random seed is picked per render
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_checkpoint(mut self, path: PathBuf) -> Self {
+        // written after every pass so an interrupted render can be resumed
+        self.checkpoint = Some(path);
+        self
+    }
+
+    pub fn frame_size(&self) -> Option<(u32, u32)> {
+        // the size of the whole frame, every view of the rig, unless it has more pixels
+        // than a u32 can count
+        let (width, height, _) = self.dimensions();
+        self.rig.frame_size(width, height).filter(|(width, height)| width.checked_mul(*height).is_some())
+    }
+
+    pub fn max_samples_per_px(&self) -> u32 {
+        // the most samples a pixel can get
+        self.adaptive.map_or(self.samples_per_px, |a| a.max_spp)
+    }
+
+    pub fn build(&self) -> Result<Camera, RenderError> {
+        // checks the settings make sense before setting up the camera
+        self.validate()?;
+        Ok(Camera::init(self))
+    }
+
+    fn vfov(&self) -> f32 {
+        self.lens.map_or(self.vfov, |lens| lens.vfov())
+    }
+
+    fn lens_radius(&self) -> f32 {
+        // in scene units
+        match self.lens {
+            Some(lens) => lens.aperture_radius(),
+            None => self.focus_distance * self.defocus_angle.div(2.0).tan(),
         }
     }
 
-    pub fn default() -> Self {
+    fn dimensions(&self) -> (u32, u32, f32) {
+        // image width, height and the aspect ratio of the viewport
+        match self.width {
+            Some(width) => (width, self.image_height, width as f32 / self.image_height as f32),
+            None => ((self.image_height as f32 * self.aspect_ratio) as u32, self.image_height, self.aspect_ratio),
+        }
+    }
+
+    fn validate(&self) -> Result<(), RenderError> {
+        // settings Camera::init can't build a sensible camera from
+
+        let invalid = |msg: String| Err(RenderError::InvalidConfig(msg));
+        let (width, height, aspect_ratio) = self.dimensions();
+        let view = self.look_from - self.look_at;
+
+        if self.width.is_none() && !(aspect_ratio.is_finite() && aspect_ratio > 0.0) {
+            return invalid(format!("aspect ratio {aspect_ratio} should be positive"));
+        }
+        if width == 0 || height == 0 {
+            return invalid(format!("image size {width}x{height} is empty"));
+        }
+        let Some((frame_width, frame_height)) = self.frame_size() else {
+            return invalid(format!("image size {width}x{height} has too many pixels for the {:?} rig", self.rig));
+        };
+        if self.samples_per_px == 0 {
+            return invalid("samples per pixel should be at least 1".to_string());
+        }
+        if let Some(lens) = self.lens && !(lens.focal_length > 0.0 && lens.f_number > 0.0) {
+            return invalid(format!("focal length {}mm and f-number {} should be positive",
+                lens.focal_length, lens.f_number));
+        }
+        if !(self.vfov() > 0.0 && self.vfov() < PI) {
+            return invalid(format!("vertical field of view {} should be between 0 and 180 degrees",
+                self.vfov().to_degrees()));
+        }
+        if self.cats_eye.is_nan() || self.cats_eye < 0.0 {
+            return invalid(format!("cat's eye strength {} should be 0 or more", self.cats_eye));
+        }
+        match self.projection {
+            Projection::Orthographic { view_height } if view_height.is_nan() || view_height <= 0.0 =>
+                return invalid(format!("orthographic view height {view_height} should be positive")),
+            Projection::Fisheye { fov } if !(fov > 0.0 && fov <= 2.0 * PI) =>
+                return invalid(format!("fisheye field of view {} should be between 0 and 360 degrees",
+                    fov.to_degrees())),
+            _ => {}
+        }
+        if !(self.shutter.open.is_finite() && self.shutter.close.is_finite() && self.shutter.open <= self.shutter.close) {
+            return invalid(format!("shutter closes at {} before it opens at {}", self.shutter.close, self.shutter.open));
+        }
+        if let ShutterCurve::Trapezoid { ramp } = self.shutter.curve && !(0.0..=0.5).contains(&ramp) {
+            return invalid(format!("shutter ramp {ramp} should be in 0..0.5"));
+        }
+        if let Rig::Stereo { eye_separation, .. } = self.rig
+            && !(eye_separation.is_finite() && eye_separation >= 0.0) {
+            return invalid(format!("eye separation {eye_separation} should be positive"));
+        }
+        if view.mag_sq() == 0.0 {
+            return invalid("look_from and look_at are the same point".to_string());
+        }
+        if self.vertical_up.cross(view).mag_sq() == 0.0 {
+            return invalid("the up vector is parallel to the view direction".to_string());
+        }
+        if !(self.focus_distance > 0.0 && (0.0..PI).contains(&self.defocus_angle)) {
+            return invalid(format!("focus distance {} should be positive and defocus angle {} \
+                between 0 and 180 degrees", self.focus_distance, self.defocus_angle.to_degrees()));
+        }
+        if let Some(a) = self.adaptive && (a.threshold.is_nan() || a.threshold <= 0.0) {
+            return invalid(format!("adaptive sampling threshold {} should be positive", a.threshold));
+        }
+        if self.progressive.as_ref().is_some_and(|p| p.pass_spp == 0) {
+            return invalid("progressive passes should take at least 1 sample per pixel".to_string());
+        }
+        if let Some((window, _)) = self.crop {
+            let (x, y) = window.pixel_ranges(frame_width, frame_height);
+            if x.is_empty() || y.is_empty() {
+                return invalid(format!("the crop window is outside the {frame_width}x{frame_height} frame"));
+            }
+        }
+        if let Some(job) = self.job && job.index >= job.count {
+            return invalid(format!("job {}/{} doesn't exist", job.index, job.count));
+        }
+        if let Some(job) = self.job && job.split == JobSplit::Samples {
+            if self.adaptive.is_some() {
+                return invalid("adaptive sampling needs all of a pixel's samples, so can't be split by \
+                    samples".to_string());
+            }
+            if job.sample_range(self.samples_per_px).is_empty() {
+                return invalid(format!("job {}/{} gets none of the {} samples per pixel",
+                    job.index, job.count, self.samples_per_px));
+            }
+        }
+        if !self.aovs.is_empty() && self.job.is_some() {
+            return invalid("aovs can't be split between jobs".to_string());
+        }
+        if let Some(denoiser) = self.denoiser && (denoiser.strength.is_nan() || denoiser.strength <= 0.0) {
+            return invalid(format!("denoising strength {} should be positive", denoiser.strength));
+        }
+        if self.denoiser.is_some() && self.job.is_some() {
+            return invalid("denoising needs the whole frame, so can't be split between jobs".to_string());
+        }
+        if self.debug.is_some() && (!self.aovs.is_empty() || self.denoiser.is_some()) {
+            return invalid("debug shading has no lighting to save aovs of or denoise".to_string());
+        }
+        if self.debug.is_some() && (self.job.is_some() || self.checkpoint.is_some()) {
+            return invalid("debug renders are scaled to the whole image, so can't be split between jobs \
+                or checkpointed".to_string());
+        }
+        if (!self.aovs.is_empty() || self.denoiser.is_some()) && self.checkpoint.is_some() {
+            return invalid("aovs and the denoiser's guides aren't kept in checkpoints, so can't be used \
+                with checkpointing".to_string());
+        }
+        if !self.aovs.is_empty() && matches!(self.crop, Some((_, CropOutput::Patch))) {
+            return invalid("aovs can't be patched into an existing image".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for CameraSetup {
+    fn default() -> Self {
         CameraSetup {
             image_height: 720,
             aspect_ratio: 16.0 / 9.0,
+            width: None,
             samples_per_px: 32,
             max_depth: 64,
             vfov: std::f32::consts::PI / 2.0,
+            lens: None,
             look_from: Vec3::new(0.0, 0.0, 0.0),
             look_at: Vec3::new(0.0, 0.0, -1.0),
             vertical_up: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
-            focus_distance: 1.0
+            focus_distance: 1.0,
+            aperture: ApertureShape::Circle,
+            cats_eye: 0.0,
+            shutter: Shutter::default(),
+            projection: Projection::Perspective,
+            rig: Rig::Mono,
+            sky: Sky::Gradient,
+            sampler: SamplerKind::Independent,
+            filter: Filter::Box { radius: 0.5 },
+            exr_precision: ExrPrecision::Half,
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            adaptive: None,
+            progressive: None,
+            time_limit: None,
+            crop: None,
+            job: None,
+            aovs: Vec::new(),
+            denoiser: None,
+            debug: None,
+            observers: Vec::new(),
+            cancel: None,
+            seed: None,
+            checkpoint: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::sync::Arc;
+    use image::GenericImageView;
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::progress::RenderProgress;
+    use crate::sampler::IndependentSampler;
+    use crate::scene::final_render_scene;
+    use crate::sphere::Sphere;
+
+    // cancels the render once its first pass is done, like killing it then
+    struct StopAfterPass(CancelToken);
+
+    impl RenderObserver for StopAfterPass {
+        fn on_pass(&self, _progress: &RenderProgress, _frame: &FrameBuffer) {
+            self.0.cancel();
+        }
+    }
+
+    fn world() -> HittableList {
+        let mut world = HittableList::new();
+        let material = Arc::new(Lambertian::new(Vec3::new(0.6, 0.4, 0.2)));
+        world.add(Box::new(Sphere::new(Vec3::new(0.0, 0.0, -1.0), 0.5, material.clone())));
+        world.add(Box::new(Sphere::new(Vec3::new(0.0, -100.5, -1.0), 100.0, material)));
+        world
+    }
+
+    fn setup() -> CameraSetup {
+        CameraSetup::default()
+            .with_image_height(20)
+            .with_samples_per_px(8)
+            .with_progressive(ProgressiveRendering {
+                pass_spp: 2,
+                snapshot_passes: None,
+                snapshot_interval: None,
+                snapshot_name: String::new(),
+            })
+            .with_defocus_angle(0.05)
+            .with_seed(11)
+            .with_aperture(ApertureShape::Polygon { blades: 6, rotation: 0.0 })
+    }
+
+    fn film_state(camera: &Camera) -> Vec<u8> {
+        let mut state = Vec::new();
+        camera.film.write_state(&mut state).unwrap();
+        state
+    }
+
+    #[test]
+    fn crop_windows_need_a_unit() {
+        assert_eq!("0,0,1,1".parse(), Ok(CropWindow::Normalised { x0: 0.0, y0: 0.0, x1: 1.0, y1: 1.0 }));
+        assert_eq!("0, 0, 0.5, 1".parse(), Ok(CropWindow::Normalised { x0: 0.0, y0: 0.0, x1: 0.5, y1: 1.0 }));
+        assert_eq!("0,0,1,1px".parse(), Ok(CropWindow::Pixels { x0: 0, y0: 0, x1: 1, y1: 1 }));
+        assert!("64,32,192,160".parse::<CropWindow>().is_err());
+        assert!("0.5,0,1,1px".parse::<CropWindow>().is_err());
+        assert!("4,4,4,8px".parse::<CropWindow>().is_err());
+    }
+
+    #[test]
+    fn crops_match_the_full_frame() {
+        // a box filtered pixel only depends on its own samples, so a crop renders exactly
+        // the pixels of the whole frame it covers, whether saved alone or patched in
+        let setup = || CameraSetup::default().with_image_height(18).with_samples_per_px(4).with_seed(3);
+        let path = |name: &str| env::temp_dir().join(format!("crop_test_{name}_{}.png", process::id()));
+        let window = "8,4,20,12px".parse().unwrap();
+        let world = world();
+
+        let mut full = setup().build().unwrap();
+        full.render(&world).unwrap();
+        full.save_to(&path("full")).unwrap();
+        let full = image::open(path("full")).unwrap().into_rgb8();
+
+        let mut cropped = setup().with_crop(window, CropOutput::Cropped).build().unwrap();
+        cropped.render(&world).unwrap();
+        cropped.save_to(&path("cropped")).unwrap();
+        let cropped = image::open(path("cropped")).unwrap().into_rgb8();
+        assert_eq!(cropped, full.view(8, 4, 12, 8).to_image());
+
+        // patched into a black frame, which stays black around the window
+        RgbImage::new(full.width(), full.height()).save(path("patched")).unwrap();
+        let mut patched = setup().with_crop(window, CropOutput::Patch).build().unwrap();
+        patched.render(&world).unwrap();
+        patched.save_to(&path("patched")).unwrap();
+        let patched = image::open(path("patched")).unwrap().into_rgb8();
+
+        for name in ["full", "cropped", "patched"] {
+            fs::remove_file(path(name)).unwrap();
+        }
+
+        for (x, y, px) in patched.enumerate_pixels() {
+            let inside = (8..20).contains(&x) && (4..12).contains(&y);
+            assert_eq!(*px, if inside { *full.get_pixel(x, y) } else { Rgb([0, 0, 0]) }, "at {x} {y}");
+        }
+    }
+
+    #[test]
+    fn merged_jobs_match_a_single_render() {
+        // a tent filter splats across tile edges, so tile jobs have to add up their borders
+        let setup = || CameraSetup::default()
+            .with_image_height(36)
+            .with_samples_per_px(6)
+            .with_filter(Filter::Tent { radius: 1.0 })
+            .with_seed(7);
+        let world = world();
+
+        let mut single = setup().build().unwrap();
+        single.render(&world).unwrap();
+
+        // the jobs sum the same samples in a different order, so agree up to rounding
+        for split in [JobSplit::Tiles, JobSplit::Samples] {
+            let paths: Vec<PathBuf> = (0..3)
+                .map(|index| {
+                    let path = env::temp_dir().join(format!("merge_test_{split:?}_{index}_{}.ckpt", process::id()));
+                    let mut job = setup().with_job(RenderJob { index, count: 3, split }).build().unwrap();
+                    job.render(&world).unwrap();
+                    job.write_partial(&path).unwrap();
+                    path
+                })
+                .collect();
+
+            let mut merged = setup().build().unwrap();
+            merged.merge_partials(&paths).unwrap();
+            paths.iter().for_each(|path| fs::remove_file(path).unwrap());
+
+            for (x, y) in iproduct!(0..merged.width, 0..merged.height) {
+                let (a, b) = (merged.film.pixel(x, y), single.film.pixel(x, y));
+                let error = (a - b).abs().component_max() / b.component_max().max(1e-3);
+                assert!(error < 1e-5, "{split:?} jobs give {a:?} at {x} {y}, not {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn merge_rejects_partials_of_another_frame() {
+        let path = |name: &str| env::temp_dir().join(format!("merge_frame_test_{name}_{}.ckpt", process::id()));
+        let job = |index| RenderJob { index, count: 2, split: JobSplit::Tiles };
+        let world = world();
+
+        let mut first = setup().with_job(job(0)).build().unwrap();
+        first.render(&world).unwrap();
+        first.write_partial(&path("first")).unwrap();
+
+        // a different seed lays the samples out differently, and a different size doesn't
+        // fit the film at all
+        let mut reseeded = setup().with_seed(12).with_job(job(1)).build().unwrap();
+        reseeded.render(&world).unwrap();
+        reseeded.write_partial(&path("reseeded")).unwrap();
+        let mut resized = setup().with_image_height(24).with_job(job(1)).build().unwrap();
+        resized.render(&world).unwrap();
+        resized.write_partial(&path("resized")).unwrap();
+
+        let merge = |second: &str| setup().build().unwrap().merge_partials(&[path("first"), path(second)]);
+        let results = [merge("reseeded"), merge("resized")];
+        for name in ["first", "reseeded", "resized"] {
+            fs::remove_file(path(name)).unwrap();
+        }
+
+        for result in results {
+            assert!(matches!(result, Err(RenderError::InvalidConfig(msg)) if msg.contains("different frame")));
+        }
+    }
+
+    #[test]
+    fn rejects_frames_with_too_many_pixels() {
+        let stereo = Rig::Stereo {
+            eye_separation: 0.1,
+            convergence: Convergence::Parallel,
+            layout: StereoLayout::SideBySide,
+        };
+        let setups = [
+            CameraSetup::default().with_image_height(100_000).with_width(100_000),
+            CameraSetup::default().with_image_height(40_000).with_rig(Rig::CubeMap),
+            CameraSetup::default().with_image_height(1).with_width(u32::MAX / 2 + 1).with_rig(stereo),
+        ];
+
+        for setup in setups {
+            assert!(matches!(setup.build(), Err(RenderError::InvalidConfig(msg)) if msg.contains("too many pixels")));
+        }
+    }
+
+    #[test]
+    fn resumed_render_matches_uninterrupted() {
+        let world = world();
+        let path = env::temp_dir().join(format!("resume_test_{}.ckpt", process::id()));
+
+        let mut whole = setup().build().unwrap();
+        whole.render(&world).unwrap();
+
+        let cancel = CancelToken::new();
+        let mut killed = setup()
+            .with_checkpoint(path.clone())
+            .with_cancel_token(cancel.clone())
+            .with_observer(Arc::new(StopAfterPass(cancel)))
+            .build().unwrap();
+        killed.render(&world).unwrap();
+        assert_eq!(killed.passes_done, 1);
+
+        let mut resumed = setup().with_checkpoint(path.clone()).build().unwrap();
+        resumed.resume(&path).unwrap();
+        resumed.render(&world).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resumed.passes_done, whole.passes_done);
+        assert!(film_state(&resumed) == film_state(&whole));
+    }
+
+    #[test]
+    fn material_ids_match_the_scene() {
+        // the final scene from its usual viewpoint, with every sample at the same time so
+        // the moving spheres hold still
+        let world = final_render_scene(7, 1);
+        let mut camera = CameraSetup::default()
+            .with_image_height(27)
+            .with_samples_per_px(2)
+            .with_vfov(20.0_f32.to_radians())
+            .with_look_from(Vec3::new(13.0, 2.0, 3.0))
+            .with_look_at(Vec3::zero())
+            .with_shutter(Shutter { open: 0.0, close: 0.0, curve: ShutterCurve::Box })
+            .with_aovs(vec![Aov::MaterialId])
+            .with_seed(2)
+            .build().unwrap();
+        camera.render(&world).unwrap();
+        let (_, ids) = &camera.aov_values()[0];
+
+        // wherever the corners and centre of a pixel see the same sphere, or all the sky, its
+        // samples did too
+        let mut sampler = IndependentSampler::new(0);
+        let mut id_at = |x: f32, y: f32| {
+            let ray = camera.get_ray(&camera.views[0], (x, y), &mut sampler).unwrap();
+            world.hit(&ray, 0.001..f32::INFINITY).map_or(0, |rec| rec.material_id)
+        };
+
+        let mut checked = Vec::new();
+        for (y, x) in iproduct!(0..camera.height, 0..camera.width) {
+            let (x0, y0) = (x as f32, y as f32);
+            let seen = [(0.01, 0.01), (0.99, 0.01), (0.01, 0.99), (0.99, 0.99), (0.5, 0.5)]
+                .map(|(dx, dy)| id_at(x0 + dx, y0 + dy));
+            if seen.iter().any(|&id| id != seen[0]) { continue }
+
+            assert_eq!(ids[(y * camera.width + x) as usize].x, seen[0] as f32, "at {x} {y}");
+            checked.push(seen[0]);
         }
+
+        // the sky, the ground (the first material) and the three big spheres (the last) are
+        // all in view
+        for id in [0, 1, 22 * 22 + 2, 22 * 22 + 3, 22 * 22 + 4] {
+            assert!(checked.contains(&id), "no material {id} in {checked:?}");
+        }
+    }
+
+    #[test]
+    fn resume_rejects_different_optics() {
+        let path = env::temp_dir().join(format!("optics_test_{}.ckpt", process::id()));
+
+        let camera = setup().build().unwrap();
+        write_checkpoint(&path, &camera.settings(), 1, &camera.film).unwrap();
+
+        let mut other = setup().with_aperture(ApertureShape::Circle).build().unwrap();
+        let result = other.resume(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(RenderError::InvalidConfig(_))));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn adaptive_sampling_stops_between_min_and_max() {
+        let (min_spp, max_spp) = (4, 64);
+        let mut camera = CameraSetup::default()
+            .with_image_height(16)
+            .with_adaptive_sampling(min_spp, max_spp, 0.01)
+            .with_seed(5)
+            .build().unwrap();
+        camera.render(&world()).unwrap();
+
+        let counts = camera.sample_counts();
+        let width = camera.width as usize;
+        assert!(counts.iter().all(|c| (min_spp..=max_spp).contains(c)), "{counts:?}");
+
+        // the top rows only see the smooth sky, so stop as soon as they're allowed to, while
+        // the noisy diffuse sphere and ground need every sample
+        assert!(counts[..2 * width].iter().all(|&c| c == min_spp), "{:?}", &counts[..2 * width]);
+        assert_eq!(counts.iter().max(), Some(&max_spp));
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use crate::film::{Film, Filter};
+use crate::job::{JobSplit, RenderJob};
+use crate::sampler::{AdaptiveSampling, SamplerKind};
+
+// checkpoint files hold everything needed to continue a render where it stopped: the
+// settings that decide which samples get taken, the number of finished passes and the
+// film's accumulation state. the sampler is stateless given the seed, so that is its state.
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    // the pixels samples are taken in
+    pub region_x: Range<u32>,
+    pub region_y: Range<u32>,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub max_depth: u32,
+    pub max_spp: u32,
+    pub pass_spp: u32,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub job: Option<RenderJob>,
+    // a hash of the camera's views, lens, aperture, shutter and sky
+    pub optics: u64,
+}
+
+impl RenderSettings {
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+
+        write_u32(w, self.width)?;
+        write_u32(w, self.height)?;
+        for v in [self.region_x.start, self.region_x.end, self.region_y.start, self.region_y.end] {
+            write_u32(w, v)?;
+        }
+        w.write_all(&self.seed.to_le_bytes())?;
+
+        let sampler: u8 = match self.sampler {
+            SamplerKind::Independent => 0,
+            SamplerKind::Stratified => 1,
+            SamplerKind::Halton => 2,
+            SamplerKind::Sobol => 3,
+        };
+        w.write_all(&[sampler])?;
+
+        let (tag, params): (u8, [f32; 3]) = match self.filter {
+            Filter::Box { radius } => (0, [radius, 0.0, 0.0]),
+            Filter::Tent { radius } => (1, [radius, 0.0, 0.0]),
+            Filter::Gaussian { radius, sigma } => (2, [radius, sigma, 0.0]),
+            Filter::Mitchell { radius, b, c } => (3, [radius, b, c]),
+            Filter::Lanczos { radius, tau } => (4, [radius, tau, 0.0]),
+        };
+        w.write_all(&[tag])?;
+        for p in params { write_f32(w, p)? }
+
+        write_u32(w, self.max_depth)?;
+        write_u32(w, self.max_spp)?;
+        write_u32(w, self.pass_spp)?;
+
+        match self.adaptive {
+            Some(adaptive) => {
+                w.write_all(&[1])?;
+                write_u32(w, adaptive.min_spp)?;
+                write_u32(w, adaptive.max_spp)?;
+                write_f32(w, adaptive.threshold)?
+            }
+            None => w.write_all(&[0])?
+        }
+
+        match self.job {
+            Some(job) => {
+                let split: u8 = match job.split {
+                    JobSplit::Tiles => 0,
+                    JobSplit::Samples => 1,
+                };
+                w.write_all(&[1, split])?;
+                write_u32(w, job.index)?;
+                write_u32(w, job.count)?
+            }
+            None => w.write_all(&[0])?
+        }
+
+        w.write_all(&self.optics.to_le_bytes())
+
+    }
+
+    fn read(r: &mut impl Read) -> io::Result<Self> {
+
+        let width = read_u32(r)?;
+        let height = read_u32(r)?;
+        let region_x = read_u32(r)?..read_u32(r)?;
+        let region_y = read_u32(r)?..read_u32(r)?;
+        let mut seed = [0u8; 8];
+        r.read_exact(&mut seed)?;
+
+        let sampler = match read_u8(r)? {
+            0 => SamplerKind::Independent,
+            1 => SamplerKind::Stratified,
+            2 => SamplerKind::Halton,
+            3 => SamplerKind::Sobol,
+            other => return Err(invalid_data(format!("unknown sampler tag {other}")))
+        };
+
+        let tag = read_u8(r)?;
+        let [p0, p1, p2] = [read_f32(r)?, read_f32(r)?, read_f32(r)?];
+        let filter = match tag {
+            0 => Filter::Box { radius: p0 },
+            1 => Filter::Tent { radius: p0 },
+            2 => Filter::Gaussian { radius: p0, sigma: p1 },
+            3 => Filter::Mitchell { radius: p0, b: p1, c: p2 },
+            4 => Filter::Lanczos { radius: p0, tau: p1 },
+            other => return Err(invalid_data(format!("unknown filter tag {other}")))
+        };
+
+        let max_depth = read_u32(r)?;
+        let max_spp = read_u32(r)?;
+        let pass_spp = read_u32(r)?;
+
+        let adaptive = match read_u8(r)? {
+            0 => None,
+            _ => Some(AdaptiveSampling {
+                min_spp: read_u32(r)?,
+                max_spp: read_u32(r)?,
+                threshold: read_f32(r)?,
+            })
+        };
+
+        let job = match read_u8(r)? {
+            0 => None,
+            _ => {
+                let split = match read_u8(r)? {
+                    0 => JobSplit::Tiles,
+                    1 => JobSplit::Samples,
+                    other => return Err(invalid_data(format!("unknown job split tag {other}")))
+                };
+                Some(RenderJob { index: read_u32(r)?, count: read_u32(r)?, split })
+            }
+        };
+
+        let mut optics = [0u8; 8];
+        r.read_exact(&mut optics)?;
+
+        Ok(RenderSettings {
+            width,
+            height,
+            region_x,
+            region_y,
+            seed: u64::from_le_bytes(seed),
+            sampler,
+            filter,
+            max_depth,
+            max_spp,
+            pass_spp,
+            adaptive,
+            job,
+            optics: u64::from_le_bytes(optics),
+        })
+
+    }
+
+    pub fn same_frame(&self, other: &RenderSettings) -> bool {
+        // whether two renders take their samples from the same frame, so their films can
+        // be merged. the job and the pass size don't change what the samples are
+        RenderSettings { job: None, pass_spp: 0, ..self.clone() }
+            == RenderSettings { job: None, pass_spp: 0, ..other.clone() }
+    }
+
+}
+
+pub fn write_checkpoint(path: &Path, settings: &RenderSettings, passes_done: u32, film: &Film) -> io::Result<()> {
+    // written next to the target and renamed over it, so a render killed while saving
+    // still leaves the previous checkpoint intact
+
+    let tmp_path = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+
+    w.write_all(MAGIC)?;
+    write_u32(&mut w, VERSION)?;
+    settings.write(&mut w)?;
+    write_u32(&mut w, passes_done)?;
+    film.write_state(&mut w)?;
+
+    w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn read_settings(path: &Path) -> io::Result<RenderSettings> {
+    let mut r = BufReader::new(File::open(path)?);
+    read_header(&mut r)
+}
+
+pub fn read_checkpoint(path: &Path, film: &mut Film) -> io::Result<(RenderSettings, u32)> {
+    // fills film with the saved state, returning the settings and finished pass count
+
+    let mut r = BufReader::new(File::open(path)?);
+
+    let settings = read_header(&mut r)?;
+    let passes_done = read_u32(&mut r)?;
+    film.read_state(&mut r)?;
+
+    Ok((settings, passes_done))
+}
+
+fn read_header(r: &mut impl Read) -> io::Result<RenderSettings> {
+
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a checkpoint file".to_string()));
+    }
+
+    let version = read_u32(r)?;
+    if version != VERSION {
+        return Err(invalid_data(format!("unsupported checkpoint version {version}")));
+    }
+
+    RenderSettings::read(r)
+
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_u32(w: &mut impl Write, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn write_f32(w: &mut impl Write, v: f32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f32(r: &mut impl Read) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
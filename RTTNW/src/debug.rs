@@ -0,0 +1,54 @@
+use std::str::FromStr;
+use ultraviolet::Vec3;
+use crate::material::MaterialKind;
+
+// what a debug render shows in place of the path traced colour, for finding problems
+// with the geometry rather than the lighting. misses are black
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugShading {
+    // the normal the shading sees, from -1..1 to 0..1
+    Normal,
+    // the texture coordinates of the hit, u as red and v as green
+    Uv,
+    // how far along the camera ray the first hit is, scaled to the farthest in the image
+    Distance,
+    // green where rays hit the outside of a surface, red where they hit the inside
+    FrontFace,
+    // a colour for each kind of material
+    Material,
+    // how many surfaces a path bounces off before it escapes, is absorbed or runs out of
+    // depth, as a log scale heatmap up to the most in the image
+    Bounces,
+    // how many bounding box and object intersection tests the camera ray takes to find
+    // its first hit, as a log scale heatmap up to the most in the image
+    Cost,
+}
+
+impl FromStr for DebugShading {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "normal" => Ok(DebugShading::Normal),
+            "uv" => Ok(DebugShading::Uv),
+            "distance" => Ok(DebugShading::Distance),
+            "front-face" => Ok(DebugShading::FrontFace),
+            "material" => Ok(DebugShading::Material),
+            "bounces" => Ok(DebugShading::Bounces),
+            "cost" => Ok(DebugShading::Cost),
+            _ => Err(format!("unknown debug shading '{s}'"))
+        }
+    }
+}
+
+pub fn material_colour(kind: MaterialKind) -> Vec3 {
+    match kind {
+        MaterialKind::Lambertian => Vec3::new(0.9, 0.6, 0.2),
+        MaterialKind::Metal => Vec3::new(0.5, 0.6, 0.9),
+        MaterialKind::Dielectric => Vec3::new(0.3, 0.9, 0.8),
+    }
+}
+
+pub fn front_face_colour(front_face: bool) -> Vec3 {
+    if front_face { Vec3::new(0.1, 0.8, 0.1) } else { Vec3::new(0.9, 0.1, 0.1) }
+}
@@ -0,0 +1,190 @@
+use rayon::prelude::*;
+use ultraviolet::Vec3;
+use crate::film::luminance;
+
+// B3 spline taps of each level of the a-trous filter
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// how quickly the weight falls off as normals turn away from each other, and as albedos
+// differ
+const NORMAL_POWER: f32 = 64.0;
+const ALBEDO_SIGMA: f32 = 0.1;
+// albedo channels darker than this aren't divided out of the colour
+const MIN_ALBEDO: f32 = 1e-3;
+
+// edge-avoiding a-trous wavelet filter (Dammertz et al. 2010) for the float film. each
+// level is a 5x5 blur with its taps twice as far apart as the last, weighted down across
+// edges in the first-hit normal and albedo, and across differences in brightness larger
+// than the pixels' own noise (as in SVGF, tracking the variance through the levels)
+#[derive(Clone, Copy, Debug)]
+pub struct Denoiser {
+    // levels, reaching 2 * (2^levels - 1) pixels out
+    pub iterations: u32,
+    // how many standard deviations of noise a brightness difference can be and still blur
+    pub strength: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser { iterations: 5, strength: 4.0 }
+    }
+}
+
+impl Denoiser {
+
+    pub fn apply(
+        &self, width: usize, height: usize, radiance: &[Vec3], albedo: &[Vec3], normal: &[Vec3], variance: &[f32]
+    ) -> Vec<Vec3> {
+        // the filtered radiance of a width * height image, row-major, given the variance of
+        // each pixel's mean luminance
+
+        // the light arriving at the surfaces (the colour over its albedo) is what's filtered,
+        // so texture in the albedo stays as sharp as the guide is
+        let divisor = |a: Vec3| Vec3::new(
+            if a.x > MIN_ALBEDO { a.x } else { 1.0 },
+            if a.y > MIN_ALBEDO { a.y } else { 1.0 },
+            if a.z > MIN_ALBEDO { a.z } else { 1.0 },
+        );
+
+        let mut colour: Vec<Vec3> = radiance.iter().zip(albedo)
+            .map(|(&c, &a)| c / divisor(a))
+            .collect();
+        let mut variance: Vec<f32> = variance.iter().zip(albedo)
+            .map(|(&v, &a)| v / luminance(divisor(a)).powi(2))
+            .collect();
+
+        for level in 0..self.iterations {
+            let step = 1 << level;
+            // the variance estimates are noisy themselves, so the weights use them blurred
+            let sigma = blur(width, height, &variance);
+
+            (colour, variance) = (0..width * height).into_par_iter()
+                .map(|p| {
+                    let (x, y) = (p % width, p / width);
+                    let (l_p, n_p, a_p) = (luminance(colour[p]), normal[p], albedo[p]);
+                    let scale = self.strength * sigma[p].max(0.0).sqrt() + 1e-4;
+
+                    let mut sum = Vec3::zero();
+                    let mut var_sum = 0.0;
+                    let mut weight_sum = 0.0;
+
+                    for (j, &ky) in KERNEL.iter().enumerate() {
+                        let Some(qy) = (y + j * step).checked_sub(2 * step).filter(|&qy| qy < height) else { continue };
+
+                        for (i, &kx) in KERNEL.iter().enumerate() {
+                            let Some(qx) = (x + i * step).checked_sub(2 * step).filter(|&qx| qx < width) else { continue };
+                            let q = qy * width + qx;
+
+                            let w_normal = normal_weight(n_p, normal[q]);
+                            let w_albedo = (-(a_p - albedo[q]).mag_sq() / (ALBEDO_SIGMA * ALBEDO_SIGMA)).exp();
+                            let w_colour = (-(l_p - luminance(colour[q])).abs() / scale).exp();
+                            let w = kx * ky * w_normal * w_albedo * w_colour;
+
+                            sum += w * colour[q];
+                            var_sum += w * w * variance[q];
+                            weight_sum += w;
+                        }
+                    }
+
+                    // the pixel itself always has a weight, so weight_sum is never 0
+                    (sum / weight_sum, var_sum / (weight_sum * weight_sum))
+                })
+                .unzip();
+        }
+
+        colour.iter().zip(albedo)
+            .map(|(&c, &a)| c * divisor(a))
+            .collect()
+    }
+
+}
+
+fn normal_weight(n_p: Vec3, n_q: Vec3) -> f32 {
+    // pixels where nothing was hit have no normal, and only blur with each other
+    match (n_p.mag_sq() > 0.0, n_q.mag_sq() > 0.0) {
+        (false, false) => 1.0,
+        (true, true) => n_p.dot(n_q).max(0.0).powf(NORMAL_POWER),
+        _ => 0.0,
+    }
+}
+
+fn blur(width: usize, height: usize, values: &[f32]) -> Vec<f32> {
+    // 3x3 binomial blur, renormalised at the edges
+    const TAPS: [f32; 3] = [0.25, 0.5, 0.25];
+
+    (0..width * height)
+        .map(|p| {
+            let (x, y) = (p % width, p / width);
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+
+            for (j, &ky) in TAPS.iter().enumerate() {
+                let Some(qy) = (y + j).checked_sub(1).filter(|&qy| qy < height) else { continue };
+                for (i, &kx) in TAPS.iter().enumerate() {
+                    let Some(qx) = (x + i).checked_sub(1).filter(|&qx| qx < width) else { continue };
+                    sum += kx * ky * values[qy * width + qx];
+                    weight_sum += kx * ky;
+                }
+            }
+
+            sum / weight_sum
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 16;
+
+    fn halves<T: Copy>(left: T, right: T) -> Vec<T> {
+        (0..SIZE * SIZE).map(|p| if p % SIZE < SIZE / 2 { left } else { right }).collect()
+    }
+
+    #[test]
+    fn leaves_a_constant_image_alone() {
+        let radiance = vec![Vec3::new(0.3, 0.5, 0.7); SIZE * SIZE];
+        let albedo = vec![Vec3::new(0.6, 0.5, 0.4); SIZE * SIZE];
+        let normal = vec![Vec3::unit_z(); SIZE * SIZE];
+        let variance = vec![0.01; SIZE * SIZE];
+
+        let denoised = Denoiser::default().apply(SIZE, SIZE, &radiance, &albedo, &normal, &variance);
+
+        for (d, r) in denoised.iter().zip(&radiance) {
+            assert!((*d - *r).abs().component_max() < 1e-5, "{d:?} isn't {r:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_edges_in_the_guides() {
+        // the halves are lit differently, and their pixels are said to be so noisy that the
+        // difference alone wouldn't stop the blur, so only the guides can keep them apart
+        let (dim, bright) = (Vec3::broadcast(0.2), Vec3::broadcast(1.0));
+        let variance = vec![10.0; SIZE * SIZE];
+        let grey = Vec3::broadcast(0.5);
+        let (red, blue) = (Vec3::new(0.8, 0.2, 0.2), Vec3::new(0.2, 0.2, 0.8));
+
+        let cases = [
+            // a crease between two faces of the same colour
+            (halves(grey, grey), halves(Vec3::unit_z(), Vec3::unit_x())),
+            // two colours on one flat face
+            (halves(red, blue), halves(Vec3::unit_z(), Vec3::unit_z())),
+        ];
+
+        for (albedo, normal) in cases {
+            let radiance: Vec<Vec3> = halves(dim, bright).iter().zip(&albedo).map(|(&l, &a)| l * a).collect();
+            let denoised = Denoiser::default().apply(SIZE, SIZE, &radiance, &albedo, &normal, &variance);
+
+            for (p, (d, r)) in denoised.iter().zip(&radiance).enumerate() {
+                assert!((*d - *r).abs().component_max() < 1e-3, "pixel {p} is {d:?}, not {r:?}");
+            }
+        }
+
+        // without an edge in either guide, the lighting does bleed across
+        let albedo = halves(grey, grey);
+        let radiance: Vec<Vec3> = halves(dim, bright).iter().map(|&l| l * grey).collect();
+        let normal = halves(Vec3::unit_z(), Vec3::unit_z());
+        let denoised = Denoiser::default().apply(SIZE, SIZE, &radiance, &albedo, &normal, &variance);
+        assert!((denoised[SIZE / 2 - 1] - radiance[SIZE / 2 - 1]).component_max() > 0.05);
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::io;
+use image::error::{EncodingError, ImageError};
+use image::ImageFormat;
+
+// everything that can stop a frame from being set up, rendered or saved
+#[derive(Debug)]
+pub enum RenderError {
+    Io(io::Error),
+    // the image crate or the exr writer failing to encode or decode an image
+    Image(ImageError),
+    // a scene description that doesn't parse, with the line it failed on (from 1)
+    Scene { line: usize, message: String },
+    // camera settings that can't make a picture, or that don't match a file being read
+    InvalidConfig(String),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::Io(e) => write!(f, "{e}"),
+            RenderError::Image(e) => write!(f, "{e}"),
+            RenderError::Scene { line, message } => write!(f, "line {line}: {message}"),
+            RenderError::InvalidConfig(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Io(e) => Some(e),
+            RenderError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(e: io::Error) -> Self {
+        RenderError::Io(e)
+    }
+}
+
+impl From<ImageError> for RenderError {
+    fn from(e: ImageError) -> Self {
+        // the image crate wraps the io errors of its readers and writers
+        match e {
+            ImageError::IoError(e) => RenderError::Io(e),
+            e => RenderError::Image(e),
+        }
+    }
+}
+
+impl From<png::EncodingError> for RenderError {
+    fn from(e: png::EncodingError) -> Self {
+        match e {
+            png::EncodingError::IoError(e) => RenderError::Io(e),
+            e => RenderError::Image(ImageError::Encoding(EncodingError::new(ImageFormat::Png.into(), e))),
+        }
+    }
+}
+
+impl From<exr::error::Error> for RenderError {
+    fn from(e: exr::error::Error) -> Self {
+        match e {
+            exr::error::Error::Io(e) => RenderError::Io(e),
+            e => RenderError::Image(ImageError::Encoding(EncodingError::new(ImageFormat::OpenExr.into(), e))),
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use std::f32::consts::PI;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::str::FromStr;
+use image::{Rgb, RgbImage};
+use itertools::iproduct;
+use ultraviolet::Vec3;
+use crate::tonemap::{srgb_encode, ToneMap};
+
+// pixel reconstruction filters. offsets are in pixels from the pixel centre.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, sigma: f32 },
+    Mitchell { radius: f32, b: f32, c: f32 },
+    Lanczos { radius: f32, tau: f32 },
+}
+
+impl Filter {
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => radius
+        }
+    }
+
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        // separable filters, so the weight is the product of the 1D profiles
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        // each profile but Lanczos' is scaled to integrate to 1 over its radius
+        let x = x.abs();
+
+        if x > self.radius() { return 0.0 }
+
+        match *self {
+            Filter::Box { radius } => 0.5 / radius,
+            Filter::Tent { radius } => (radius - x) / (radius * radius),
+            Filter::Gaussian { radius, sigma } => {
+                // shifted down so the filter reaches zero at its radius
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                let integral = sigma * (2.0 * PI).sqrt() * erf(radius / (sigma * 2f32.sqrt()))
+                    - 2.0 * radius * gaussian(radius);
+                (gaussian(x) - gaussian(radius)).max(0.0) / integral
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2.0 * x / radius;
+
+                let weight = if x > 1.0 {
+                    ((-b - 6.0 * c) * x.powi(3) + (6.0 * b + 30.0 * c) * x.powi(2)
+                        + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2) + (6.0 - 2.0 * b)) / 6.0
+                };
+                weight * 2.0 / radius
+            }
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau)
+        }
+    }
+
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "box" => Ok(Filter::Box { radius: 0.5 }),
+            "tent" => Ok(Filter::Tent { radius: 1.0 }),
+            "gaussian" => Ok(Filter::Gaussian { radius: 1.5, sigma: 0.5 }),
+            "mitchell" => Ok(Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+            "lanczos" => Ok(Filter::Lanczos { radius: 2.0, tau: 2.0 }),
+            _ => Err(format!("unknown filter '{s}'"))
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 { return 1.0 }
+    (PI * x).sin() / (PI * x)
+}
+
+fn erf(x: f32) -> f32 {
+    // Abramowitz and Stegun 7.1.26, good to about 1e-7 for x >= 0
+    let t = 1.0 / (1.0 + 0.327_591_1 * x);
+    let poly = t * (0.254_829_6 + t * (-0.284_496_74 + t * (1.421_413_7 + t * (-1.453_152 + t * 1.061_405_4))));
+    1.0 - poly * (-x * x).exp()
+}
+
+#[derive(Clone, Copy, Default)]
+struct FilmPixel {
+    rgb_sum: Vec3,
+    weight_sum: f32,
+}
+
+// running mean and variance (Welford) of the luminance of the samples taken in a pixel,
+// independent of where the filter splatted them
+#[derive(Clone, Copy, Default)]
+pub struct PixelStats {
+    count: u32,
+    mean: f32,
+    m2: f32,
+}
+
+// pixels whose filter weights sum to less than this are left black. the negative lobes of
+// the Mitchell and Lanczos filters can cancel a pixel's weights out entirely, and dividing
+// by what's left would blow its few samples up
+const MIN_WEIGHT: f32 = 1e-4;
+
+// below this mean luminance the relative error is measured against a floor instead,
+// so near-black pixels don't need an unbounded number of samples
+const MIN_LUMINANCE: f32 = 1e-3;
+
+impl PixelStats {
+
+    fn add(&mut self, luminance: f32) {
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    fn combine(&mut self, other: PixelStats) {
+        // merges the statistics of two disjoint sets of samples (Chan et al.)
+
+        if other.count == 0 { return }
+        if self.count == 0 { *self = other; return }
+
+        let (n_a, n_b) = (self.count as f32, other.count as f32);
+        let n = n_a + n_b;
+        let delta = other.mean - self.mean;
+
+        self.mean += delta * n_b / n;
+        self.m2 += other.m2 + delta * delta * n_a * n_b / n;
+        self.count += other.count;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn relative_error(&self) -> f32 {
+        // standard error of the mean relative to the mean
+        if self.count < 2 { return f32::INFINITY }
+
+        let variance = self.m2 / (self.count - 1) as f32;
+        (variance / self.count as f32).sqrt() / self.mean.max(MIN_LUMINANCE)
+    }
+
+    pub fn mean_variance(&self) -> f32 {
+        // variance of the mean luminance. a single sample is taken to be as uncertain as
+        // it is bright
+        if self.count < 2 { return self.mean * self.mean }
+
+        self.m2 / (self.count - 1) as f32 / self.count as f32
+    }
+
+}
+
+pub fn luminance(col: Vec3) -> f32 {
+    // Rec. 709 luma weights for linear rgb
+    col.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+// float RGB accumulation buffer. samples are splatted into every pixel within the
+// filter radius and normalised by the summed filter weights when read back.
+#[derive(Clone)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+    stats: Vec<PixelStats>,
+}
+
+impl Film {
+
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Film {
+            width,
+            height,
+            filter,
+            pixels: vec![FilmPixel::default(); width as usize * height as usize],
+            stats: vec![PixelStats::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn tile(&self, x: Range<u32>, y: Range<u32>, clip_x: Range<u32>, clip_y: Range<u32>) -> FilmTile {
+        // a tile that accepts samples from pixels in x * y. it is padded by the filter
+        // radius so splats that land on neighbouring tiles are kept, as far as the edges of
+        // clip_x * clip_y, which is the whole film unless it holds several images.
+
+        let pad = self.padding();
+        let x0 = x.start.saturating_sub(pad).max(clip_x.start);
+        let y0 = y.start.saturating_sub(pad).max(clip_y.start);
+        let x1 = (x.end + pad).min(clip_x.end).min(self.width);
+        let y1 = (y.end + pad).min(clip_y.end).min(self.height);
+
+        // the stats only cover the pixels samples are taken in, starting from the film's
+        let stats = iproduct!(y.clone(), x.clone())
+            .map(|(py, px)| self.stats[(py * self.width + px) as usize])
+            .collect();
+
+        FilmTile {
+            x0, y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            filter: self.filter,
+            pixels: vec![FilmPixel::default(); ((x1 - x0) * (y1 - y0)) as usize],
+            core_x: x,
+            core_y: y,
+            stats,
+        }
+    }
+
+    pub fn padding(&self) -> u32 {
+        // how many pixels away from the one it was taken in a sample can splat into
+        (self.filter.radius() - 0.5).ceil().max(0.0) as u32
+    }
+
+    pub fn merge_tile(&mut self, tile: FilmTile) {
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                let src = tile.pixels[(ty * tile.width + tx) as usize];
+                let dst = &mut self.pixels[((tile.y0 + ty) * self.width + tile.x0 + tx) as usize];
+
+                dst.rgb_sum += src.rgb_sum;
+                dst.weight_sum += src.weight_sum;
+            }
+        }
+
+        let core_width = tile.core_x.len() as u32;
+        for (i, stats) in tile.stats.into_iter().enumerate() {
+            let (px, py) = (tile.core_x.start + i as u32 % core_width, tile.core_y.start + i as u32 / core_width);
+            self.stats[(py * self.width + px) as usize] = stats;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Film) {
+        // adds the samples accumulated by another film of the same size, such as a
+        // partial render of the same frame
+
+        for (dst, src) in self.pixels.iter_mut().zip(&other.pixels) {
+            dst.rgb_sum += src.rgb_sum;
+            dst.weight_sum += src.weight_sum;
+        }
+
+        for (dst, src) in self.stats.iter_mut().zip(&other.stats) {
+            dst.combine(*src);
+        }
+    }
+
+    pub fn stats(&self, x: u32, y: u32) -> PixelStats {
+        self.stats[(y * self.width + x) as usize]
+    }
+
+    pub fn write_state(&self, w: &mut impl Write) -> io::Result<()> {
+        // raw accumulation state, little-endian, so a render can be continued bit-exactly
+
+        for (px, stats) in self.pixels.iter().zip(&self.stats) {
+            for v in [px.rgb_sum.x, px.rgb_sum.y, px.rgb_sum.z, px.weight_sum] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&stats.count.to_le_bytes())?;
+            w.write_all(&stats.mean.to_le_bytes())?;
+            w.write_all(&stats.m2.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_state(&mut self, r: &mut impl Read) -> io::Result<()> {
+        // counterpart of write_state for a film of the same size
+
+        let mut buf = [0u8; 4];
+        let mut next = |r: &mut dyn Read| -> io::Result<[u8; 4]> {
+            r.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+
+        for (px, stats) in self.pixels.iter_mut().zip(self.stats.iter_mut()) {
+            px.rgb_sum.x = f32::from_le_bytes(next(r)?);
+            px.rgb_sum.y = f32::from_le_bytes(next(r)?);
+            px.rgb_sum.z = f32::from_le_bytes(next(r)?);
+            px.weight_sum = f32::from_le_bytes(next(r)?);
+            stats.count = u32::from_le_bytes(next(r)?);
+            stats.mean = f32::from_le_bytes(next(r)?);
+            stats.m2 = f32::from_le_bytes(next(r)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        // filtered radiance estimate for pixel x y
+        let px = self.pixels[(y * self.width + x) as usize];
+
+        if px.weight_sum > MIN_WEIGHT { px.rgb_sum / px.weight_sum } else { Vec3::zero() }
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, radiance: Vec3) {
+        // replaces the estimate for pixel x y, as by a denoiser. its samples stay the same
+        self.pixels[(y * self.width + x) as usize] = FilmPixel { rgb_sum: radiance, weight_sum: 1.0 };
+    }
+
+    pub fn radiance(&self, x: Range<u32>, y: Range<u32>) -> Vec<Vec3> {
+        // linear radiance of the pixels in x * y, row-major from the top left
+        iproduct!(y, x)
+            .map(|(y, x)| self.pixel(x, y))
+            .collect()
+    }
+
+    pub fn to_image(&self, x: Range<u32>, y: Range<u32>, exposure: f32, tone_map: ToneMap) -> RgbImage {
+        // quantises the pixels in x * y to 8 bits. exposure is in stops (EV)
+
+        let scale = exposure.exp2();
+
+        RgbImage::from_fn(x.len() as u32, y.len() as u32, |px, py| {
+            let mut col = tone_map.apply(scale * self.pixel(x.start + px, y.start + py));
+
+            // sRGB transfer function
+            col.apply(srgb_encode);
+            // conversion to range 0-255
+            col.apply( |x| (x * 255.0).round() );
+
+            Rgb([col.x as u8, col.y as u8, col.z as u8])
+        })
+    }
+
+}
+
+pub struct FilmTile {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+
+    core_x: Range<u32>,
+    core_y: Range<u32>,
+    stats: Vec<PixelStats>,
+}
+
+impl FilmTile {
+
+    pub fn stats(&self, x: u32, y: u32) -> PixelStats {
+        let core_width = self.core_x.len() as u32;
+        self.stats[((y - self.core_y.start) * core_width + x - self.core_x.start) as usize]
+    }
+
+    pub fn add_sample(&mut self, px: (u32, u32), p_film: (f32, f32), radiance: Vec3) {
+        // adds a sample taken in pixel px. p_film is in continuous pixel coordinates,
+        // pixel x y covering [x, x+1) * [y, y+1)
+
+        // zero NaNs and infinities rather than letting one bad path ruin the neighbourhood
+        let radiance = if radiance.x.is_finite() && radiance.y.is_finite() && radiance.z.is_finite() {
+            radiance
+        } else {
+            Vec3::zero()
+        };
+
+        let core_width = self.core_x.len() as u32;
+        let idx = ((px.1 - self.core_y.start) * core_width + px.0 - self.core_x.start) as usize;
+        self.stats[idx].add(luminance(radiance));
+
+        let radius = self.filter.radius();
+        let (sx, sy) = (p_film.0 - 0.5, p_film.1 - 0.5);
+
+        let x_min = ((sx - radius).ceil().max(self.x0 as f32)) as u32;
+        let y_min = ((sy - radius).ceil().max(self.y0 as f32)) as u32;
+        let x_max = ((sx + radius).floor() as i64).min((self.x0 + self.width) as i64 - 1);
+        let y_max = ((sy + radius).floor() as i64).min((self.y0 + self.height) as i64 - 1);
+
+        for y in y_min as i64..=y_max {
+            for x in x_min as i64..=x_max {
+                let weight = self.filter.evaluate(x as f32 - sx, y as f32 - sy);
+                if weight == 0.0 { continue }
+
+                let idx = ((y as u32 - self.y0) * self.width + (x as u32 - self.x0)) as usize;
+                self.pixels[idx].rgb_sum += weight * radiance;
+                self.pixels[idx].weight_sum += weight;
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::iproduct;
+
+    const FILTERS: [&str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+    #[test]
+    fn filters_integrate_to_one() {
+        for name in ["box", "tent", "gaussian", "mitchell"] {
+            let filter: Filter = name.parse().unwrap();
+            let radius = filter.radius();
+
+            // midpoint rule over the filter's square support
+            let n = 400;
+            let step = 2.0 * radius / n as f32;
+            let integral: f32 = iproduct!(0..n, 0..n)
+                .map(|(i, j)| {
+                    let (x, y) = (-radius + (i as f32 + 0.5) * step, -radius + (j as f32 + 0.5) * step);
+                    filter.evaluate(x, y) * step * step
+                })
+                .sum();
+
+            assert!((integral - 1.0).abs() < 1e-3, "{name} integrates to {integral}");
+        }
+    }
+
+    #[test]
+    fn constant_radiance_stays_constant() {
+        let radiance = Vec3::new(1.0, 0.5, 0.25);
+
+        for name in FILTERS {
+            let mut film = Film::new(6, 5, name.parse().unwrap());
+            let mut tile = film.tile(0..6, 0..5, 0..6, 0..5);
+
+            for (py, px, sy, sx) in iproduct!(0..5, 0..6, 0..4, 0..4) {
+                let p_film = (px as f32 + (sx as f32 + 0.5) / 4.0, py as f32 + (sy as f32 + 0.5) / 4.0);
+                tile.add_sample((px, py), p_film, radiance);
+            }
+            film.merge_tile(tile);
+
+            for (x, y) in iproduct!(0..6, 0..5) {
+                let error = (film.pixel(x, y) - radiance).abs().component_max();
+                assert!(error < 1e-5, "{name} filter gives {:?} at {x} {y}", film.pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_weights_leave_a_pixel_black() {
+        // only the negative lobe of the Mitchell filter reaches pixel 2 from a sample at the
+        // right edge of pixel 0
+        let mut film = Film::new(4, 1, "mitchell".parse().unwrap());
+        let mut tile = film.tile(0..1, 0..1, 0..4, 0..1);
+        tile.add_sample((0, 0), (0.99, 0.5), Vec3::one());
+        film.merge_tile(tile);
+
+        assert_eq!(film.pixel(2, 0), Vec3::zero());
+        assert!(film.pixel(0, 0).x.is_finite());
+    }
+}
@@ -1,6 +1,8 @@
+use std::f32::consts::PI;
 use std::ops::Range;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use ultraviolet::Vec3;
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
 
@@ -8,41 +10,229 @@ pub struct HitRecord {
     pub point: Vec3,
     pub normal: Vec3,
     pub material: Arc<dyn Material>,
+    // the scene's number for the material, from 1, or 0 if it wasn't given one
+    pub material_id: u32,
     pub time: f32,
-    pub front_face: bool
+    pub front_face: bool,
+    // the outward normal in the object's own space, before any transform
+    pub local_normal: Vec3,
+    // place of the object hit in the innermost list holding it
+    pub object_id: Option<u32>,
+}
+
+impl HitRecord {
+
+    pub fn uv(&self) -> (f32, f32) {
+        // texture coordinates in 0..1, mapped from the local normal as on a sphere (which
+        // every object is): u around the y axis starting from -x, v from the bottom pole
+        let n = self.local_normal;
+        let theta = (-n.y).acos();
+        let phi = (-n.z).atan2(n.x) + PI;
+
+        (phi / (2.0 * PI), theta / PI)
+    }
+
 }
 
 pub trait Hittable: Sync + Send {
 
     fn hit(&self, ray: &Ray, t_interval: Range<f32>) -> Option<HitRecord>;
 
+    // bounds of everywhere the object is, at any time
+    fn bounding_box(&self) -> Aabb;
+
+    // how many bounding box and object intersection tests hit makes for ray
+    fn hit_cost(&self, _ray: &Ray, _t_interval: Range<f32>) -> u32 { 1 }
+
 }
 
+// objects are found through a bounding volume hierarchy over the list, built the first
+// time a ray is traced and again after the list changes
 pub struct HittableList {
     vec: Vec<Box<dyn Hittable>>,
+    bounds: Aabb,
+    bvh: OnceLock<Bvh>,
+}
+
+// nodes in depth-first order, so an inner node's first child is the node after it
+struct Bvh {
+    nodes: Vec<BvhNode>,
+    // indices into the list, each leaf's together
+    order: Vec<u32>,
 }
 
+struct BvhNode {
+    bounds: Aabb,
+    // a leaf's objects are order[start..start + count]. an inner node has a count of 0
+    // and its second child at start
+    start: u32,
+    count: u32,
+}
+
+// the most objects a leaf holds
+const LEAF_SIZE: usize = 2;
+// deeper than the tree for any list that fits in memory
+const MAX_DEPTH: usize = 64;
+
 impl HittableList {
 
     pub fn new() -> Self {
-        HittableList { vec: Vec::new() }
+        HittableList { vec: Vec::new(), bounds: Aabb::empty(), bvh: OnceLock::new() }
     }
 
     pub fn add(&mut self, hittable: Box<dyn Hittable>) {
+        self.bounds = self.bounds.union(&hittable.bounding_box());
         self.vec.push(hittable);
+        self.bvh = OnceLock::new();
     }
 
     pub fn _clear(&mut self) {
         self.vec.clear();
+        self.bounds = Aabb::empty();
+        self.bvh = OnceLock::new();
+    }
+
+    fn closest_hit(
+        &self, ray: &Ray, t_interval: Range<f32>, mut on_test: impl FnMut(Option<&dyn Hittable>, Range<f32>)
+    ) -> Option<HitRecord> {
+        // walks the nodes whose bounds the ray enters before the closest hit so far,
+        // telling on_test about every bounding box test (with None) and object test
+
+        let bvh = self.bvh.get_or_init(|| Bvh::build(&self.vec));
+        if bvh.nodes.is_empty() { return None }
+
+        let mut closest = None;
+        let mut t_max = t_interval.end;
+        let mut stack = [0u32; MAX_DEPTH];
+        let mut depth = 1;
+
+        while depth > 0 {
+            depth -= 1;
+            let index = stack[depth];
+            let node = &bvh.nodes[index as usize];
+
+            on_test(None, t_interval.start..t_max);
+            if !node.bounds.hit(ray, t_interval.start..t_max) { continue }
+
+            if node.count == 0 {
+                stack[depth] = node.start;
+                stack[depth + 1] = index + 1;
+                depth += 2;
+                continue;
+            }
+
+            for &i in &bvh.order[node.start as usize..(node.start + node.count) as usize] {
+                let object = self.vec[i as usize].as_ref();
+                on_test(Some(object), t_interval.start..t_max);
+
+                if let Some(mut rec) = object.hit(ray, t_interval.start..t_max) {
+                    rec.object_id.get_or_insert(i);
+                    t_max = rec.time;
+                    closest = Some(rec);
+                }
+            }
+        }
+
+        closest
+    }
+
+}
+
+impl Bvh {
+
+    fn build(objects: &[Box<dyn Hittable>]) -> Self {
+        // splits the objects at the median of their centres along the longest axis of
+        // the centres' bounds. objects with empty bounds can't be hit, so are left out
+
+        let mut items: Vec<(u32, Aabb)> = objects.iter()
+            .enumerate()
+            .map(|(i, object)| (i as u32, object.bounding_box()))
+            .filter(|(_, bounds)| !bounds.is_empty())
+            .collect();
+
+        let mut bvh = Bvh { nodes: Vec::new(), order: Vec::with_capacity(items.len()) };
+        if !items.is_empty() { bvh.add_node(&mut items) }
+        bvh
+    }
+
+    fn add_node(&mut self, items: &mut [(u32, Aabb)]) {
+        let bounds = items.iter().fold(Aabb::empty(), |b, (_, item)| b.union(item));
+        let node = self.nodes.len();
+
+        if items.len() <= LEAF_SIZE {
+            self.nodes.push(BvhNode { bounds, start: self.order.len() as u32, count: items.len() as u32 });
+            self.order.extend(items.iter().map(|(i, _)| *i));
+            return;
+        }
+
+        let centre = |b: &Aabb| (b.min + b.max) * 0.5;
+        let centres = items.iter().fold(Aabb::empty(), |b, (_, item)| b.union(&Aabb::around(centre(item), 0.0)));
+        let extent = centres.max - centres.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |(_, a), (_, b)| centre(a)[axis].total_cmp(&centre(b)[axis]));
+
+        self.nodes.push(BvhNode { bounds, start: 0, count: 0 });
+        let (first, second) = items.split_at_mut(mid);
+        self.add_node(first);
+        self.nodes[node].start = self.nodes.len() as u32;
+        self.add_node(second);
     }
 
 }
 
 impl Hittable for HittableList {
     fn hit(&self, ray: &Ray, t_interval: Range<f32>) -> Option<HitRecord> {
-        self.vec.iter()
-            .filter_map(|x| x.hit(ray, t_interval.clone()))
-            .min_by(|a, b| a.time.partial_cmp(&b.time).unwrap())
+        self.closest_hit(ray, t_interval, |_, _| {})
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn hit_cost(&self, ray: &Ray, t_interval: Range<f32>) -> u32 {
+        let mut cost = 0;
+        self.closest_hit(ray, t_interval, |object, t_interval| {
+            cost += object.map_or(1, |object| object.hit_cost(ray, t_interval));
+        });
+        cost
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use super::*;
+    use crate::scene::final_render_scene;
+
+    #[test]
+    fn bvh_finds_the_closest_hit() {
+        // the same first hits as trying every object in turn, for rays from all over the
+        // final scene, in fewer tests where the rays miss most of it
+        let world = final_render_scene(3, 1);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut costs = Vec::new();
+
+        for _ in 0..2000 {
+            let mut random = |min: f32, max: f32| rng.random_range(min..max);
+            let origin = Vec3::new(random(-15.0, 15.0), random(0.1, 4.0), random(-15.0, 15.0));
+            let direction = Vec3::new(random(-1.0, 1.0), random(-1.0, 0.2), random(-1.0, 1.0));
+            let ray = Ray::new(origin, direction, rng.random());
+
+            let every = world.vec.iter()
+                .enumerate()
+                .filter_map(|(i, object)| object.hit(&ray, 0.001..f32::INFINITY).map(|rec| (i as u32, rec.time)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let found = world.hit(&ray, 0.001..f32::INFINITY).map(|rec| (rec.object_id.unwrap(), rec.time));
+
+            assert_eq!(found, every, "from {origin:?} along {direction:?}");
+            costs.push(world.hit_cost(&ray, 0.001..f32::INFINITY));
+        }
+
+        let (least, most) = (costs.iter().min().unwrap(), costs.iter().max().unwrap());
+        assert!(least < most && (*most as usize) < world.vec.len(), "costs from {least} to {most}");
+    }
+}
@@ -0,0 +1,71 @@
+use std::ops::Range;
+use std::str::FromStr;
+
+// one of `count` jobs a frame is split into so separate processes can render it. every job
+// writes a partial render that the merge command sums into the final image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderJob {
+    pub index: u32,
+    pub count: u32,
+    pub split: JobSplit,
+}
+
+// tile jobs take every count-th tile of the frame, sample jobs take a contiguous range
+// of the sample indices of every pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JobSplit {
+    Tiles,
+    Samples,
+}
+
+impl RenderJob {
+
+    pub fn takes_tile(&self, tile_index: usize) -> bool {
+        // tiles are dealt out in turn rather than in blocks, so jobs see a similar mix
+        // of cheap and expensive parts of the frame
+        self.split == JobSplit::Samples || tile_index % self.count as usize == self.index as usize
+    }
+
+    pub fn sample_range(&self, samples_per_px: u32) -> Range<u32> {
+        // the sample indices this job takes in every pixel
+        match self.split {
+            JobSplit::Tiles => 0..samples_per_px,
+            JobSplit::Samples => {
+                let share = |i: u32| (samples_per_px as u64 * i as u64 / self.count as u64) as u32;
+                share(self.index)..share(self.index + 1)
+            }
+        }
+    }
+
+}
+
+impl FromStr for RenderJob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "index/count", zero based. the split defaults to tiles
+
+        let Some((index, count)) = s.split_once('/') else {
+            return Err(format!("job '{s}' should be index/count"));
+        };
+
+        let index: u32 = index.trim().parse().map_err(|_| format!("bad job index in '{s}'"))?;
+        let count: u32 = count.trim().parse().map_err(|_| format!("bad job count in '{s}'"))?;
+
+        if index >= count { return Err(format!("job index in '{s}' should be below the count")) }
+
+        Ok(RenderJob { index, count, split: JobSplit::Tiles })
+    }
+}
+
+impl FromStr for JobSplit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tiles" => Ok(JobSplit::Tiles),
+            "samples" => Ok(JobSplit::Samples),
+            _ => Err(format!("unknown job split '{s}'"))
+        }
+    }
+}
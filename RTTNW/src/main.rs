@@ -1,105 +1,444 @@
+mod aabb;
+mod animation;
+mod aov;
+mod aperture;
 mod camera;
+mod checkpoint;
+mod debug;
+mod denoise;
+mod error;
+mod film;
 mod ray;
+mod rig;
 mod hittable;
+mod job;
 mod sphere;
 mod material;
+mod output;
+mod progress;
+mod sampler;
+mod scene;
+mod sequence;
+mod shutter;
+mod server;
+mod sky;
+mod tonemap;
 
-use crate::camera::{random_unit_vec, Camera, CameraSetup};
+use crate::animation::{turntable, CameraKey, CameraPath, Interpolation};
+use crate::aov::Aov;
+use crate::aperture::{ApertureMask, ApertureShape};
+use crate::camera::{Camera, CameraSetup, CropOutput, ProgressiveRendering, Projection};
+use crate::checkpoint::read_settings;
+use crate::debug::DebugShading;
+use crate::denoise::Denoiser;
+use crate::film::Filter;
 use crate::hittable::HittableList;
-use crate::material::{Lambertian, Metal, Dielectric, Material};
-use crate::sphere::{MovingSphere, Sphere};
-use rand::{random, random_range, SeedableRng};
-use std::ops::{Div, Mul};
+use crate::job::{JobSplit, RenderJob};
+use crate::output::ExrPrecision;
+use crate::rig::{Convergence, Rig, StereoLayout};
+use crate::progress::ProgressBarObserver;
+use crate::sampler::SamplerKind;
+use crate::scene::{final_render_scene, parse_camera_path};
+use crate::sequence::{FrameRange, Sequence};
+use crate::shutter::{Shutter, ShutterCurve};
+use crate::sky::Sky;
+use crate::tonemap::ToneMap;
+use rand::random;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
-use itertools::iproduct;
+use std::time::Duration;
 use ultraviolet::Vec3;
 
+// flags that are on when given, and take no value
+const SWITCHES: [&str; 3] = ["--skip-existing", "--no-dither", "--denoise"];
+
 fn main() {
 
     println!("Ray Tracing The Next Weekend.\n\
               =============================");
 
-    // scene setup
-    let world = final_render_scene();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `render` (the default) renders a frame, or one job of it. `merge` takes the same
+    // options plus the partial renders of the jobs, and combines them into the frame
+    // `serve` runs the HTTP render service instead
+    if args.first().is_some_and(|a| a == "serve") {
+        let addr = arg_value(&args, "--bind").unwrap_or("127.0.0.1:7878");
+        server::serve(addr).unwrap_or_else(|e| exit_with(&format!("render service failed: {e}")));
+        return;
+    }
+
+    let (merge, args) = match args.first().map(|a| a.as_str()) {
+        Some("merge") => (true, &args[1..]),
+        Some("render") => (false, &args[1..]),
+        _ => (false, &args[..]),
+    };
+    let partials: Vec<PathBuf> = if merge { positional_args(args).map(PathBuf::from).collect() } else { Vec::new() };
+    if merge && partials.is_empty() { exit_with("merge needs the partial renders to combine") }
+
+    // rendering for a wall-clock budget instead, with --spp as the upper bound
+    let time_limit: Option<f32> = parse_arg(args, "--time-limit");
+    let samples_per_px = parse_arg(args, "--spp")
+        .unwrap_or(if time_limit.is_some() { 65536 } else { 128 });
+    let sampler = parse_arg(args, "--sampler").unwrap_or(SamplerKind::Independent);
+    let filter = parse_arg(args, "--filter").unwrap_or(Filter::Box { radius: 0.5 });
+    let exr_precision = parse_arg(args, "--exr-precision").unwrap_or(ExrPrecision::Half);
+    let exposure = parse_arg(args, "--exposure").unwrap_or(0.0);
+    let tone_map = parse_arg(args, "--tonemap").unwrap_or(ToneMap::Clamp);
+    // gradient, or daylight[:elevation,azimuth,turbidity]
+    let sky = parse_arg(args, "--sky").unwrap_or(Sky::Gradient);
+    // perspective, orthographic[:view height], fisheye[:degrees] or equirectangular
+    let projection = parse_arg(args, "--projection").unwrap_or(Projection::Perspective);
+    // a photographic lens in mm on a full frame sensor, instead of the field of view and
+    // defocus angle. the aperture can be a polygon, or shaped by a grey image
+    let focal_length: Option<f32> = parse_arg(args, "--focal-length");
+    let f_number = parse_arg(args, "--f-number").unwrap_or(2.8);
+    let aperture = match arg_value(args, "--aperture-mask") {
+        Some(path) => ApertureShape::Mask(Arc::new(ApertureMask::open(path.as_ref())
+            .unwrap_or_else(|e| exit_with(&format!("can't read aperture mask {path}: {e}"))))),
+        None => parse_arg(args, "--aperture").unwrap_or(ApertureShape::Circle),
+    };
+    let cats_eye = parse_arg(args, "--cats-eye").unwrap_or(0.0);
+    // the shutter interval motion blur covers, given as open,close or as a frame of an
+    // animation shot with a rotary shutter. moving objects move from time 0 to 1
+    let shutter_curve = parse_arg(args, "--shutter-curve").unwrap_or(ShutterCurve::Box);
+    let fps: Option<f32> = parse_arg(args, "--fps");
+    let shutter_angle = parse_arg(args, "--shutter-angle").unwrap_or(180.0_f32).to_radians();
+    if fps.is_some_and(|fps| fps.is_nan() || fps <= 0.0) { exit_with("--fps needs to be positive") }
+    let shutter = match (parse_arg::<Shutter>(args, "--shutter"), fps) {
+        (Some(shutter), _) => Shutter { curve: shutter_curve, ..shutter },
+        (None, Some(fps)) => Shutter::from_frame(
+            parse_arg(args, "--frame").unwrap_or(0), fps, shutter_angle, shutter_curve),
+        (None, None) => Shutter { curve: shutter_curve, ..Shutter::default() },
+    };
+    // rendering frames "first-last" of an animation instead, at --fps (default 24), each
+    // saved numbered as output_0001.png and so on, or all together when the output is a
+    // gif or apng. the scene can turn about the vertical through look_at once every
+    // --turntable seconds, and the camera can follow the keys of a --camera-path file
+    // (see scene.rs) or --orbit look_at once every so many frames (frames 0 on by default)
+    // --skip-existing leaves frames already saved alone, --no-dither keeps gifs undithered
+    let orbit: Option<u32> = parse_arg(args, "--orbit");
+    let frames = parse_arg(args, "--frames")
+        .or(orbit.map(|frames| FrameRange { first: 0, last: frames.max(1) - 1 }));
+    let skip_existing = has_flag(args, "--skip-existing");
+    let gif_dither = !has_flag(args, "--no-dither");
+    let turntable_period: Option<f32> = parse_arg(args, "--turntable");
+    let camera_interpolation = parse_arg(args, "--camera-interpolation").unwrap_or(Interpolation::Bezier);
+    let camera_path = arg_value(args, "--camera-path");
+
+    if orbit.is_some() && camera_path.is_some() { exit_with("--orbit and --camera-path both move the camera") }
+    if orbit == Some(0) { exit_with("--orbit needs at least one frame") }
+
+    if turntable_period.is_some_and(|period| period.is_nan() || period <= 0.0) {
+        exit_with("--turntable needs a positive period")
+    }
+    // a stereo pair (left eye first) or a cube map in one image, instead of a single view
+    let rig = match arg_value(args, "--rig") {
+        None | Some("mono") => Rig::Mono,
+        Some("stereo") => Rig::Stereo {
+            eye_separation: parse_arg(args, "--eye-separation").unwrap_or(0.065),
+            convergence: parse_arg(args, "--convergence").unwrap_or(Convergence::Parallel),
+            layout: parse_arg(args, "--stereo-layout").unwrap_or(StereoLayout::SideBySide),
+        },
+        Some("cube-map") => Rig::CubeMap,
+        Some(rig) => exit_with(&format!("invalid value for --rig: unknown rig '{rig}'")),
+    };
+    // adaptive sampling, with --spp as the upper bound
+    let adaptive_threshold: Option<f32> = parse_arg(args, "--adaptive-threshold");
+    let min_spp = parse_arg(args, "--min-spp").unwrap_or(16);
+    let heatmap = arg_value(args, "--spp-heatmap");
+    // progressive rendering in passes, with snapshots of the output along the way
+    let pass_spp: Option<u32> = parse_arg(args, "--pass-spp");
+    let snapshot_passes = parse_arg(args, "--snapshot-passes");
+    let snapshot_seconds: Option<f32> = parse_arg(args, "--snapshot-seconds");
+    // only render a crop window, saved on its own or patched into the existing output. the
+    // window is x0,y0,x1,y1 as fractions of the frame, or x0,y0,x1,y1px in pixels
+    let crop = parse_arg(args, "--crop");
+    let crop_output = parse_arg(args, "--crop-output").unwrap_or(CropOutput::Cropped);
+    // the extension picks the format: png, exr, hdr or pfm
+    let output = arg_value(args, "--output").unwrap_or("test.png");
+    // aovs to save as well, comma separated or "all": layers of an exr output, otherwise
+    // images named after the output (test_albedo.png)
+    let aovs: Vec<Aov> = match arg_value(args, "--aovs") {
+        Some("all") => Aov::ALL.to_vec(),
+        Some(list) => list.split(',')
+            .map(|aov| aov.trim().parse()
+                .unwrap_or_else(|e| exit_with(&format!("invalid value for --aovs: {e}"))))
+            .collect(),
+        None => Vec::new(),
+    };
+    // denoising the finished render, guided by the first hits' albedo and normals. a
+    // higher strength blurs across bigger differences in brightness
+    let denoise = has_flag(args, "--denoise");
+    let denoise_strength = parse_arg(args, "--denoise-strength").unwrap_or(Denoiser::default().strength);
+    // showing normal, uv, distance, front-face, material, bounces or cost (intersection
+    // tests) instead of path tracing
+    let debug: Option<DebugShading> = parse_arg(args, "--debug");
+    // checkpointing after every pass, and resuming from a checkpoint (which keeps
+    // checkpointing to the same file unless --checkpoint says otherwise)
+    let resume = arg_value(args, "--resume").map(PathBuf::from);
+    let checkpoint = arg_value(args, "--checkpoint").map(PathBuf::from).or(resume.clone());
+    // rendering one job ("index/count") of a frame split across processes, writing the
+    // film to the partial render file instead of saving an image
+    let job: Option<RenderJob> = parse_arg(args, "--job");
+    let split = parse_arg(args, "--split").unwrap_or(JobSplit::Tiles);
+    let partial = arg_value(args, "--partial").map(PathBuf::from);
+
+    if job.is_some() && partial.is_none() { exit_with("--job needs a --partial file to write to") }
+    if frames.is_some() && (merge || job.is_some() || resume.is_some() || checkpoint.is_some()) {
+        exit_with("--frames renders whole frames, so can't be merged, split into jobs or checkpointed");
+    }
+    if !aovs.is_empty() && merge {
+        exit_with("aovs aren't kept in partial renders, so can't be merged");
+    }
+    if denoise && merge {
+        exit_with("the denoiser's guides aren't kept in partial renders, so can't be merged");
+    }
+    if !aovs.is_empty() && frames.is_some() && Sequence::is_animation(output) {
+        exit_with("aovs can't be saved in a gif or apng");
+    }
+
+    // the seed drives both the scene layout and the sample pattern, so a resumed render
+    // or a merge takes it from the checkpoint / partial render unless one is given
+    let seed = match (parse_arg(args, "--seed"), resume.as_ref().or(partials.first())) {
+        (Some(seed), _) => seed,
+        (None, Some(path)) => read_settings(path)
+            .unwrap_or_else(|e| exit_with(&format!("can't read checkpoint {}: {e}", path.display())))
+            .seed,
+        (None, None) => random(),
+    };
+    println!("seed: {seed}");
 
     // camera setup
-    let camera_setup = CameraSetup::new(
-        480,                        // image height
-        16.0 / 9.0,                 // image aspect ratio
-        128,                        // samples per pixel
-        32,                         // max ray bounce depth
-        20.0_f32.to_radians(),      // vertical field of view
-        Vec3::new(13.0, 2.0, 3.0),  // look from position
-        Vec3::new(0.0, 0.0, 0.0),   // look at position
-        Vec3::new(0.0, 1.0, 0.0),   // vertical up Vec
-        0.6_f32.to_radians(),       // defocus angle
-        10.0                        // focus distance
-    );
-
-    let mut camera_obj = Camera::init(&camera_setup);
-
-    // render scene
-    camera_obj.render(&world);
+    let (look_from, look_at, vertical_up) = (Vec3::new(13.0, 2.0, 3.0), Vec3::zero(), Vec3::new(0.0, 1.0, 0.0));
+    let camera_setup = CameraSetup::default()
+    .with_image_height(480)
+    .with_aspect_ratio(16.0 / 9.0)
+    .with_samples_per_px(samples_per_px)
+    .with_max_depth(32)
+    .with_vfov(20.0_f32.to_radians())
+    .with_look_from(look_from)
+    .with_look_at(look_at)
+    .with_vertical_up(vertical_up)
+    .with_defocus_angle(0.6_f32.to_radians())
+    .with_focus_distance(10.0)
+    .with_aperture(aperture)
+    .with_cats_eye(cats_eye)
+    .with_shutter(shutter)
+    .with_projection(projection)
+    .with_rig(rig)
+    .with_sky(sky)
+    .with_sampler(sampler)
+    .with_filter(filter)
+    .with_exr_precision(exr_precision)
+    .with_exposure(exposure)
+    .with_tone_map(tone_map)
+    .with_aovs(aovs)
+    .with_seed(seed);
 
-    // save rendered image to file
-    camera_obj.save(Some("test.png"));
+    let camera_setup = match focal_length {
+        Some(focal_length) => camera_setup.with_lens(focal_length, f_number),
+        None => camera_setup
+    };
 
-}
+    let camera_setup = match adaptive_threshold {
+        Some(threshold) => camera_setup.with_adaptive_sampling(min_spp, samples_per_px, threshold),
+        None => camera_setup
+    };
+
+    // checkpoints are written between passes, so checkpointed renders always run in passes
+    let pass_spp = pass_spp.or(checkpoint.as_ref().map(|_| 16));
+
+    let camera_setup = match pass_spp {
+        Some(pass_spp) => camera_setup.with_progressive(ProgressiveRendering {
+            pass_spp,
+            snapshot_passes,
+            snapshot_interval: snapshot_seconds.map(Duration::from_secs_f32),
+            snapshot_name: output.to_string(),
+        }),
+        None => camera_setup
+    };
+
+    let camera_setup = match time_limit {
+        Some(seconds) => camera_setup.with_time_limit(Duration::from_secs_f32(seconds)),
+        None => camera_setup
+    };
+
+    let camera_setup = match crop {
+        Some(window) => camera_setup.with_crop(window, crop_output),
+        None => camera_setup
+    };
+
+    let camera_setup = match checkpoint {
+        Some(path) => camera_setup.with_checkpoint(path),
+        None => camera_setup
+    };
+
+    let camera_setup = match denoise {
+        true => camera_setup.with_denoiser(Denoiser { strength: denoise_strength, ..Denoiser::default() }),
+        false => camera_setup
+    };
+
+    let camera_setup = match debug {
+        Some(mode) => camera_setup.with_debug_shading(mode),
+        None => camera_setup
+    };
+
+    let camera_setup = match job {
+        Some(job) => camera_setup.with_job(RenderJob { split, ..job }),
+        None => camera_setup
+    };
+
+    if let Some(frames) = frames {
+        let fps = fps.unwrap_or(24.0);
+        let start = CameraKey {
+            time: 0.0,
+            look_from,
+            look_at,
+            vfov: 20.0_f32.to_radians(),
+            focus_distance: 10.0,
+        };
+        // when the last frame is over
+        let end_time = (frames.last + 1) as f32 / fps;
 
-fn final_render_scene() -> HittableList {
-    // setup for the final render scene
-    let mut scene = HittableList::new();
-
-    let ground_mat = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
-    scene.add(Box::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground_mat)));
-
-    for (a, b) in iproduct!(-11..11, -11..11) {
-
-        let choose_mat: f32 = random();
-        let center = Vec3::new(
-            (a as f32) + 0.9 * random::<f32>(),
-            0.2,
-            (b as f32) + 0.9 * random::<f32>());
-
-        let sphere_material: Arc<dyn Material> = if choose_mat < 0.8 {
-            // diffuse/matte material
-            let colour = random_unit_vec().mul(random_unit_vec());
-            Arc::new(Lambertian::new(colour))
-
-        } else if choose_mat < 0.95 {
-            // metal
-            let colour = Vec3::new(
-                random_range(0.5..1.0),
-                random_range(0.5..1.0),
-                random_range(0.5..1.0)
-            );
-            let fuzz = random_range(0.0..0.5);
-            Arc::new(Metal::new(colour, fuzz))
-
-        } else {
-            // dielectric material
-            Arc::new(Dielectric::new(1.5))
+        let camera_path = match (camera_path, orbit) {
+            (Some(path), _) => {
+                let text = std::fs::read_to_string(path)
+                    .unwrap_or_else(|e| exit_with(&format!("can't read camera path {path}: {e}")));
+                Some(parse_camera_path(&text, camera_interpolation, start)
+                    .unwrap_or_else(|e| exit_with(&format!("invalid camera path {path}: {e}"))))
+            }
+            (None, Some(orbit)) => Some(CameraPath::orbit(start, vertical_up, orbit as f32 / fps, end_time)),
+            (None, None) => None,
         };
 
-        if choose_mat < 0.8 {
-            let center_1 = center + Vec3::new(0.0, random_range(0.0..0.5), 0.0);
-            scene.add(Box::new(MovingSphere::new(center, center_1, 0.2, sphere_material)))
+        let sequence = Sequence { frames, fps, shutter_angle, shutter_curve, camera_path, gif_dither };
 
-        } else {
-            scene.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
+        let world = match turntable_period {
+            Some(period) => {
+                let mut world = HittableList::new();
+                world.add(Box::new(turntable(
+                    Box::new(final_render_scene(seed, 1)), look_at, vertical_up, period, end_time)));
+                world
+            }
+            None => final_render_scene(seed, 1),
+        };
+
+        // an animated image keeps its frames in memory until the end, so there are no
+        // finished frames to skip
+        let animation = Sequence::is_animation(output);
+        let mut images = Vec::new();
+
+        for frame in sequence.frames.frames() {
+            let path = (!animation).then(|| Sequence::frame_path(output, frame)
+                .unwrap_or_else(|e| exit_with(&format!("can't save frame {frame}: {e}"))));
+            if let Some(path) = &path && skip_existing && path.exists() {
+                println!("Skipping frame {frame}, {} already exists", path.display());
+                continue
+            }
+
+            println!("Frame {frame} ({}-{})", frames.first, frames.last);
+            let mut camera_obj = sequence.frame_setup(&camera_setup, frame)
+                .with_observer(Arc::new(ProgressBarObserver::new()))
+                .build()
+                .unwrap_or_else(|e| exit_with(&format!("invalid camera setup for frame {frame}: {e}")));
+
+            camera_obj.render(&world)
+                .unwrap_or_else(|e| exit_with(&format!("render of frame {frame} failed: {e}")));
+            match &path {
+                Some(path) => camera_obj.save_to(path)
+                    .unwrap_or_else(|e| exit_with(&format!("can't save {}: {e}", path.display()))),
+                None => images.push(camera_obj.image()),
+            }
         }
 
+        if animation {
+            Camera::output_path(output)
+                .and_then(|path| sequence.write_animation(&path, &images))
+                .unwrap_or_else(|e| exit_with(&format!("can't save {output}: {e}")));
+        }
+        return;
     }
 
-    let mat1 = Arc::new(Dielectric::new(1.5));
-    let mat2 = Arc::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1)));
-    let mat3 = Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
+    // a merge renders nothing, so it gets no progress bar
+    let camera_setup = if merge {
+        camera_setup
+    } else {
+        camera_setup.with_observer(Arc::new(ProgressBarObserver::new()))
+    };
 
-    scene.add(Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, mat1)));
-    scene.add(Box::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, mat2)));
-    scene.add(Box::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, mat3)));
+    let mut camera_obj = camera_setup.build()
+        .unwrap_or_else(|e| exit_with(&format!("invalid camera setup: {e}")));
 
-    scene
+    if merge {
+        camera_obj.merge_partials(&partials)
+            .unwrap_or_else(|e| exit_with(&format!("can't merge partial renders: {e}")));
+    } else {
+        if let Some(path) = &resume {
+            camera_obj.resume(path)
+                .unwrap_or_else(|e| exit_with(&format!("can't resume from {}: {e}", path.display())));
+        }
 
+        // scene setup
+        let world = final_render_scene(seed, 1);
+
+        // render scene
+        camera_obj.render(&world)
+            .unwrap_or_else(|e| exit_with(&format!("render failed: {e}")));
+    }
+
+    // a job only covers part of the frame, so it is kept as a film for merging
+    if let Some(path) = partial {
+        camera_obj.write_partial(&path)
+            .unwrap_or_else(|e| exit_with(&format!("can't write partial render {}: {e}", path.display())));
+        println!("Saved partial render to {}", path.display());
+        return;
+    }
+
+    // save rendered image to file
+    camera_obj.save(Some(output))
+        .unwrap_or_else(|e| exit_with(&format!("can't save {output}: {e}")));
+    if let Some(heatmap) = heatmap {
+        camera_obj.save_sample_heatmap(heatmap)
+            .unwrap_or_else(|e| exit_with(&format!("can't save sample heatmap {heatmap}: {e}")));
+    }
+
+}
+
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    // value following `flag` on the command line, if present
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|s| s.as_str())
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    // whether one of the SWITCHES is on the command line
+    args.iter().any(|a| a == flag)
 }
 
+fn positional_args(args: &[String]) -> impl Iterator<Item = &str> {
+    // the arguments that aren't flags, or the values following them
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        while i < args.len() && args[i].starts_with("--") {
+            i += if SWITCHES.contains(&args[i].as_str()) { 1 } else { 2 };
+        }
+        i += 1;
+        args.get(i - 1).map(|s| s.as_str())
+    })
+}
+
+fn parse_arg<T: FromStr>(args: &[String], flag: &str) -> Option<T> where T::Err: Display {
+    // parsed value of `flag`, exiting with a message if it doesn't parse
+    arg_value(args, flag).map(|s| s.parse::<T>()
+        .unwrap_or_else(|e| exit_with(&format!("invalid value for {flag}: {e}"))))
+}
+
+fn exit_with(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1)
+}
@@ -1,12 +1,27 @@
-use rand::random;
 use ultraviolet::Vec3;
 use crate::hittable::HitRecord;
-use crate::ray::{near_zero, Ray};
-use crate::ray::random_unit_vec;
+use crate::ray::{near_zero, sample_unit_vec, Ray};
+use crate::sampler::Sampler;
+
+// what a material is, for debug renders
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialKind {
+    Lambertian,
+    Metal,
+    Dielectric,
+}
 
 pub trait Material: Sync + Send {
 
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> { None }
+    fn kind(&self) -> MaterialKind;
+
+    fn scatter(&self, _ray_in: &Ray, _rec: &HitRecord, _sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> { None }
+
+    // albedo of an ideal diffuse surface, used for direct sampling of the sun
+    fn diffuse_albedo(&self) -> Option<Vec3> { None }
+
+    // the surface's colour, as an aov
+    fn albedo(&self) -> Vec3 { self.diffuse_albedo().unwrap_or(Vec3::one()) }
 
 }
 
@@ -22,16 +37,21 @@ impl Lambertian {
 
 impl Material for Lambertian {
 
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> {
-        let mut direction = rec.normal + random_unit_vec();
+    fn kind(&self) -> MaterialKind { MaterialKind::Lambertian }
+
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
+        let mut direction = rec.normal + sample_unit_vec(sampler.get_2d());
 
-        let s = 1e-8;
         if near_zero(&direction) { direction = rec.normal }
 
         Some((Ray::new(rec.point, direction, ray_in.time), self.colour))
 
     }
 
+    fn diffuse_albedo(&self) -> Option<Vec3> {
+        Some(self.colour)
+    }
+
 }
 
 pub struct Metal {
@@ -47,10 +67,12 @@ impl Metal {
 
 impl Material for Metal {
 
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn kind(&self) -> MaterialKind { MaterialKind::Metal }
+
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
 
         let reflected = ray_in.direction.reflected(rec.normal).normalized()
-            + (self.fuzz * random_unit_vec());
+            + (self.fuzz * sample_unit_vec(sampler.get_2d()));
         let scattered = Ray::new(rec.point, reflected, ray_in.time);
 
         if scattered.direction.dot(rec.normal) > 0.0 {
@@ -61,6 +83,10 @@ impl Material for Metal {
 
     }
 
+    fn albedo(&self) -> Vec3 {
+        self.colour
+    }
+
 }
 
 pub struct Dielectric {
@@ -80,7 +106,10 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, rec: &HitRecord) -> Option<(Ray, Vec3)> {
+
+    fn kind(&self) -> MaterialKind { MaterialKind::Dielectric }
+
+    fn scatter(&self, ray_in: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
         let col = Vec3::one();
         let ri = if rec.front_face { 1.0 / self.refract_idx } else { self.refract_idx };
 
@@ -90,7 +119,7 @@ impl Material for Dielectric {
         let cannot_refract = ri * sin_theta > 1.0;
 
         let dir = if cannot_refract ||
-            Dielectric::reflectance(cos_theta, ri) > random() {
+            Dielectric::reflectance(cos_theta, ri) > sampler.get_1d() {
             // reflect
             unit_dir.reflected(rec.normal)
 
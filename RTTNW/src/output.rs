@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use color_quant::NeuQuant;
+use exr::prelude::*;
+use image::{Delay, Frame, Rgb, RgbImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::hdr::HdrEncoder;
+use image::imageops::colorops::{dither, ColorMap};
+use ultraviolet::Vec3;
+
+// writers for linear-radiance image formats, and for animations of 8 bit frames. pixel
+// buffers are row-major from the top left.
+
+// NeuQuant learns the gif palette from at most this many pixels, spread over the frames
+const PALETTE_SAMPLE_PIXELS: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+impl FromStr for ExrPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "half" => Ok(ExrPrecision::Half),
+            "float" => Ok(ExrPrecision::Float),
+            _ => Err(format!("unknown exr precision '{s}'"))
+        }
+    }
+}
+
+pub fn write_exr(
+    path: &Path, width: u32, height: u32, layers: &[(&str, &[Vec3])], precision: ExrPrecision
+) -> exr::error::Result<()> {
+    // every layer becomes an R G B channel group in a single part, prefixed with the layer
+    // name ("albedo.R"). a layer with an empty name is written as the plain R G B channels.
+
+    let channel = |name: String, values: Vec<f32>| {
+        let samples = match precision {
+            ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+            ExrPrecision::Float => FlatSamples::F32(values),
+        };
+        AnyChannel::new(name.as_str(), samples)
+    };
+
+    let mut channels = SmallVec::new();
+
+    for (name, pixels) in layers {
+        let prefix = if name.is_empty() { String::new() } else { format!("{name}.") };
+
+        channels.push(channel(format!("{prefix}R"), pixels.iter().map(|p| p.x).collect()));
+        channels.push(channel(format!("{prefix}G"), pixels.iter().map(|p| p.y).collect()));
+        channels.push(channel(format!("{prefix}B"), pixels.iter().map(|p| p.z).collect()));
+    }
+
+    let image = Image::from_channels(
+        (width as usize, height as usize),
+        AnyChannels::sort(channels)
+    );
+
+    image.write().to_file(path)
+}
+
+pub fn write_hdr(path: &Path, width: u32, height: u32, pixels: &[Vec3]) -> image::ImageResult<()> {
+    // Radiance RGBE
+
+    let rgb: Vec<Rgb<f32>> = pixels.iter()
+        .map(|p| Rgb([p.x.max(0.0), p.y.max(0.0), p.z.max(0.0)]))
+        .collect();
+
+    let writer = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(writer).encode(&rgb, width as usize, height as usize)
+}
+
+pub fn write_pfm(path: &Path, width: u32, height: u32, pixels: &[Vec3]) -> io::Result<()> {
+    // portable float map: ascii header, then little-endian (negative scale) floats with
+    // the rows stored bottom to top
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "PF\n{width} {height}\n-1.0\n")?;
+
+    for row in pixels.chunks(width as usize).rev() {
+        for p in row {
+            writer.write_all(&p.x.to_le_bytes())?;
+            writer.write_all(&p.y.to_le_bytes())?;
+            writer.write_all(&p.z.to_le_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+pub fn write_gif(path: &Path, frames: &[RgbImage], fps: f32, dithered: bool) -> image::ImageResult<()> {
+    // looping forever. every frame shares one palette, so colours don't flicker from frame
+    // to frame, and each then has few enough colours for the encoder to keep them exactly.
+    // dithering (Floyd-Steinberg) trades the banding of smooth gradients for fine noise
+
+    let total = frames.iter().map(|f| (f.width() * f.height()) as usize).sum::<usize>();
+    let step = total.div_ceil(PALETTE_SAMPLE_PIXELS).max(1);
+    let samples: Vec<u8> = frames.iter()
+        .flat_map(|f| f.pixels())
+        .step_by(step)
+        .flat_map(|p| [p[0], p[1], p[2], 255])
+        .collect();
+    let palette = NeuQuant::new(10, 256, &samples);
+
+    let delay = Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / fps));
+
+    let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    for frame in frames {
+        let mut rgba = image::DynamicImage::ImageRgb8(frame.clone()).to_rgba8();
+        if dithered {
+            dither(&mut rgba, &palette);
+        } else {
+            rgba.pixels_mut().for_each(|p| palette.map_color(p));
+        }
+        encoder.encode_frame(Frame::from_parts(rgba, 0, 0, delay))?;
+    }
+
+    Ok(())
+}
+
+pub fn write_apng(path: &Path, frames: &[RgbImage], fps: f32) -> std::result::Result<(), png::EncodingError> {
+    // an animated png, looping forever, with the frames kept exactly
+
+    let (width, height) = frames.first().map_or((0, 0), |f| f.dimensions());
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    // in ms
+    encoder.set_frame_delay((1000.0 / fps).round().clamp(1.0, u16::MAX as f32) as u16, 1000)?;
+
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(frame.as_raw())?;
+    }
+    writer.finish()
+}
+
+pub fn read_pfm(path: &Path) -> io::Result<(u32, u32, Vec<Vec3>)> {
+    // counterpart of write_pfm, accepting either endianness. only colour (PF) maps
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{msg} in pfm header"));
+
+    // the header is three whitespace separated lines: type, dimensions, scale
+    let mut header = String::new();
+    for _ in 0..3 {
+        if reader.read_line(&mut header)? == 0 { return Err(invalid("unexpected end")) }
+    }
+    let mut fields = header.split_whitespace();
+
+    if fields.next() != Some("PF") { return Err(invalid("unsupported type")) }
+    let mut number = || fields.next().ok_or_else(|| invalid("missing field"));
+    let width: u32 = number()?.parse().map_err(|_| invalid("bad width"))?;
+    let height: u32 = number()?.parse().map_err(|_| invalid("bad height"))?;
+    let scale: f32 = number()?.parse().map_err(|_| invalid("bad scale"))?;
+
+    let mut data = vec![0u8; width as usize * height as usize * 12];
+    reader.read_exact(&mut data)?;
+
+    let value = |b: &[u8]| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        if scale < 0.0 { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }
+    };
+
+    let rows: Vec<Vec<Vec3>> = data.chunks(width as usize * 12)
+        .map(|row| row.chunks(12)
+            .map(|p| Vec3::new(value(&p[0..4]), value(&p[4..8]), value(&p[8..12])))
+            .collect())
+        .collect();
+
+    Ok((width, height, rows.into_iter().rev().flatten().collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use super::*;
+
+    const WIDTH: u32 = 7;
+    const HEIGHT: u32 = 5;
+
+    fn film() -> Vec<Vec3> {
+        // a spread of radiances over several orders of magnitude, different in every channel
+        (0..WIDTH * HEIGHT)
+            .map(|i| {
+                let x = i as f32;
+                Vec3::new(1e-3 * (x + 1.0), (x * 0.37).sin().abs() * 20.0 + 0.01, 1000.0 / (x + 1.0))
+            })
+            .collect()
+    }
+
+    fn assert_close(read: &[Vec3], written: &[Vec3], tolerance: impl Fn(Vec3) -> Vec3) {
+        assert_eq!(read.len(), written.len());
+
+        for (i, (r, w)) in read.iter().zip(written).enumerate() {
+            let error = (*r - *w).abs() - tolerance(*w);
+            assert!(error.component_max() <= 0.0, "pixel {i} read back as {r:?}, written as {w:?}");
+        }
+    }
+
+    #[test]
+    fn exr_round_trips() {
+        let pixels = film();
+        let albedo: Vec<Vec3> = pixels.iter().map(|p| *p * 0.5).collect();
+
+        for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+            let path = env::temp_dir().join(format!("output_test_{}_{precision:?}.exr", process::id()));
+            write_exr(&path, WIDTH, HEIGHT, &[("", &pixels), ("albedo", &albedo)], precision).unwrap();
+
+            let image = read_all_flat_layers_from_file(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            let layer = &image.layer_data[0];
+            assert_eq!(layer.size, Vec2(WIDTH as usize, HEIGHT as usize));
+
+            let layer_pixels = |prefix: &str| -> Vec<Vec3> {
+                let channel = |name: String| {
+                    let channel = layer.channel_data.list.iter().find(|c| c.name.to_string() == name).unwrap();
+                    let is_half = matches!(channel.sample_data, FlatSamples::F16(_));
+                    assert_eq!(is_half, precision == ExrPrecision::Half, "{name} stored at the wrong precision");
+                    channel
+                };
+                let [r, g, b] = ["R", "G", "B"].map(|c| channel(format!("{prefix}{c}")));
+
+                (0..pixels.len())
+                    .map(|i| Vec3::new(
+                        r.sample_data.value_by_flat_index(i).to_f32(),
+                        g.sample_data.value_by_flat_index(i).to_f32(),
+                        b.sample_data.value_by_flat_index(i).to_f32(),
+                    ))
+                    .collect()
+            };
+
+            // half floats keep 11 significant bits
+            let tolerance = match precision {
+                ExrPrecision::Half => |w: Vec3| w.abs() / 2048.0,
+                ExrPrecision::Float => |_| Vec3::zero(),
+            };
+            assert_close(&layer_pixels(""), &pixels, tolerance);
+            assert_close(&layer_pixels("albedo."), &albedo, tolerance);
+        }
+    }
+
+    #[test]
+    fn hdr_round_trips() {
+        let pixels = film();
+        let path = env::temp_dir().join(format!("output_test_{}.hdr", process::id()));
+        write_hdr(&path, WIDTH, HEIGHT, &pixels).unwrap();
+
+        let image = image::open(&path).unwrap().into_rgb32f();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(image.dimensions(), (WIDTH, HEIGHT));
+
+        // RGBE keeps 8 bits of mantissa, shared by the channels at the brightest's exponent
+        let read: Vec<Vec3> = image.pixels().map(|p| Vec3::new(p[0], p[1], p[2])).collect();
+        assert_close(&read, &pixels, |w| Vec3::broadcast(w.component_max() / 128.0));
+    }
+
+    #[test]
+    fn pfm_round_trips() {
+        let pixels = film();
+        let path = env::temp_dir().join(format!("output_test_{}.pfm", process::id()));
+        write_pfm(&path, WIDTH, HEIGHT, &pixels).unwrap();
+
+        let (width, height, read) = read_pfm(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((width, height), (WIDTH, HEIGHT));
+        assert_eq!(read, pixels);
+    }
+}
@@ -0,0 +1,221 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use image::RgbImage;
+use indicatif::{ProgressBar, ProgressStyle};
+use crate::film::Film;
+use crate::tonemap::ToneMap;
+
+// hooks for following a render from outside the camera. tiles finish on the render threads,
+// so on_progress is called from several threads at once.
+pub trait RenderObserver: Send + Sync {
+
+    // after every tile
+    fn on_progress(&self, _progress: &RenderProgress) {}
+
+    // after every pass, with the image accumulated so far
+    fn on_pass(&self, _progress: &RenderProgress, _frame: &FrameBuffer) {}
+
+    fn on_finish(&self, _summary: &RenderSummary) {}
+
+    // runs f with anything the observer draws out of the way, for the camera's own output
+    fn suspend(&self, f: &mut dyn FnMut()) { f() }
+
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderProgress {
+    // 0..1, of the time budget for a time-budgeted render
+    pub fraction: f32,
+    pub tiles_done: u64,
+    // unknown for a time-budgeted render
+    pub tiles_total: Option<u64>,
+    // the pass being rendered, from 1, and the most the render will take
+    pub pass: u32,
+    pub passes: u32,
+    pub elapsed: Duration,
+    pub eta: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSummary {
+    pub cancelled: bool,
+    pub mean_spp: f32,
+    pub min_spp: u32,
+    pub max_spp: u32,
+    pub elapsed: Duration,
+}
+
+// a view of the film's output region as it is between passes
+pub struct FrameBuffer<'a> {
+    film: &'a Film,
+    x: Range<u32>,
+    y: Range<u32>,
+    exposure: f32,
+    tone_map: ToneMap,
+}
+
+impl<'a> FrameBuffer<'a> {
+
+    pub fn new(film: &'a Film, x: Range<u32>, y: Range<u32>, exposure: f32, tone_map: ToneMap) -> Self {
+        FrameBuffer { film, x, y, exposure, tone_map }
+    }
+
+    pub fn to_image(&self) -> RgbImage {
+        // tone mapped and quantised like the final image
+        self.film.to_image(self.x.clone(), self.y.clone(), self.exposure, self.tone_map)
+    }
+
+}
+
+// stops a render from another thread, checked before every tile
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+}
+
+// the terminal progress bar
+pub struct ProgressBarObserver {
+    bar: ProgressBar,
+}
+
+// progress bar resolution
+const BAR_LENGTH: u64 = 1000;
+
+impl ProgressBarObserver {
+
+    pub fn new() -> Self {
+        let bar = ProgressBar::new(BAR_LENGTH);
+        bar.set_style(
+            ProgressStyle::with_template("elapsed: [{elapsed}] {bar:50.cyan/blue} {percent:.bold.cyan/blue}% {msg}")
+                .unwrap()
+                .progress_chars("##-"));
+
+        bar.set_message("rendering frame...");
+
+        ProgressBarObserver { bar }
+    }
+
+}
+
+impl RenderObserver for ProgressBarObserver {
+
+    fn on_progress(&self, progress: &RenderProgress) {
+        self.bar.set_position((progress.fraction * BAR_LENGTH as f32) as u64);
+
+        if progress.tiles_total.is_none() {
+            self.bar.set_message(format!("rendering pass {}...", progress.pass));
+        } else if progress.passes > 1 {
+            self.bar.set_message(format!("rendering pass {}/{}...", progress.pass, progress.passes));
+        }
+    }
+
+    fn on_finish(&self, summary: &RenderSummary) {
+        if summary.cancelled {
+            self.bar.abandon_with_message("cancelled");
+        } else {
+            self.bar.finish_with_message(format!("done, {:.1} samples per pixel ({}-{})",
+                summary.mean_spp, summary.min_spp, summary.max_spp));
+        }
+    }
+
+    fn suspend(&self, f: &mut dyn FnMut()) {
+        self.bar.suspend(f)
+    }
+
+}
+
+// progress bookkeeping for Camera::render, shared by the render threads
+pub struct ProgressTracker {
+    start: Instant,
+    time_limit: Option<Duration>,
+    tiles_per_pass: u64,
+    passes: u32,
+    // tiles done before this render started, when resuming
+    tiles_before: u64,
+    tiles_done: AtomicU64,
+    pass: AtomicU32,
+}
+
+impl ProgressTracker {
+
+    pub fn new(tiles_per_pass: u64, passes: u32, passes_done: u32, time_limit: Option<Duration>) -> Self {
+        let tiles_before = tiles_per_pass * passes_done.min(passes) as u64;
+
+        ProgressTracker {
+            start: Instant::now(),
+            time_limit,
+            tiles_per_pass,
+            passes,
+            tiles_before,
+            tiles_done: AtomicU64::new(tiles_before),
+            pass: AtomicU32::new(passes_done + 1),
+        }
+    }
+
+    pub fn start_pass(&self, pass: u32) {
+        // pass counts from 0
+        self.pass.store(pass + 1, Ordering::Relaxed);
+    }
+
+    pub fn tile_done(&self) {
+        self.tiles_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn progress(&self) -> RenderProgress {
+
+        let elapsed = self.start.elapsed();
+        let tiles_done = self.tiles_done.load(Ordering::Relaxed);
+
+        let (fraction, tiles_total, eta) = match self.time_limit {
+            Some(limit) => (
+                (elapsed.as_secs_f32() / limit.as_secs_f32()).min(1.0),
+                None,
+                Some(limit.saturating_sub(elapsed)),
+            ),
+            None => {
+                let total = self.tiles_per_pass * self.passes as u64;
+                let fraction = tiles_done as f32 / total.max(1) as f32;
+
+                // extrapolated from the tiles rendered by this run only
+                let done_now = tiles_done - self.tiles_before;
+                let eta = (done_now > 0).then(|| {
+                    elapsed.mul_f64(total.saturating_sub(tiles_done) as f64 / done_now as f64)
+                });
+
+                (fraction, Some(total), eta)
+            }
+        };
+
+        RenderProgress {
+            fraction,
+            tiles_done,
+            tiles_total,
+            pass: self.pass.load(Ordering::Relaxed),
+            passes: self.passes,
+            elapsed,
+            eta,
+        }
+
+    }
+
+}
@@ -1,4 +1,4 @@
-use rand::random_range;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
 use ultraviolet::Vec3;
 
 pub struct Ray {
@@ -19,30 +19,28 @@ impl Ray {
 
 }
 
-pub fn random_unit_vec() -> Vec3 {
-    loop {
-        let out = Vec3::new(
-            random_range(-1.0..1.0),
-            random_range(-1.0..1.0),
-            random_range(-1.0..1.0),
-        );
+pub fn sample_unit_vec(u: (f32, f32)) -> Vec3 {
+    // maps a 2D sample uniformly onto the unit sphere
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
 
-        if 1e-160 < out.mag_sq() && out.mag_sq() < 1.0 {
-            return out.normalized();
-        }
-
-    }
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = Vec3::new(
-            random_range(-1.0..1.0),
-            random_range(-1.0..1.0),
-            0.0
-        );
-        if p.mag_sq() < 1.0 { return p; }
-    }
+pub fn sample_unit_disk(u: (f32, f32)) -> Vec3 {
+    // concentric mapping of a 2D sample onto the unit disk (Shirley & Chiu)
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+
+    if a == 0.0 && b == 0.0 { return Vec3::zero() }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, FRAC_PI_4 * (b / a))
+    } else {
+        (b, FRAC_PI_2 - FRAC_PI_4 * (a / b))
+    };
+
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
 pub fn near_zero(v: &Vec3) -> bool {
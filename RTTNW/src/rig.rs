@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+// the views a camera renders side by side into one frame, all from around look_from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rig {
+    // a single view, the default
+    Mono,
+    // a left and a right eye, eye_separation apart (in world units) across the view
+    Stereo { eye_separation: f32, convergence: Convergence, layout: StereoLayout },
+    // the six 90 degree faces of a cube, each image_height square, laid out in a 3x2 grid:
+    //   +x -x +y
+    //   -y +z -z
+    // in the camera's frame: x right, y up and z back, so -z looks at look_at. the side
+    // faces have vertical_up at the top, the top of the +y face is towards +z and the
+    // top of the -y face towards -z
+    CubeMap,
+}
+
+// which way the eyes of a stereo rig look
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Convergence {
+    // both along the view direction, so only infinity has no parallax
+    Parallel,
+    // each turned in to the point focus_distance along the view direction
+    ToeIn,
+}
+
+// where the eyes go in a stereo frame, the left eye first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    SideBySide,
+    OverUnder,
+}
+
+impl Rig {
+
+    pub fn frame_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // the size of the whole frame for views of width x height, if it fits in a u32.
+        // cube faces are square
+        match self {
+            Rig::Mono => Some((width, height)),
+            Rig::Stereo { layout: StereoLayout::SideBySide, .. } => Some((width.checked_mul(2)?, height)),
+            Rig::Stereo { layout: StereoLayout::OverUnder, .. } => Some((width, height.checked_mul(2)?)),
+            Rig::CubeMap => Some((height.checked_mul(3)?, height.checked_mul(2)?)),
+        }
+    }
+
+}
+
+impl FromStr for Convergence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "parallel" => Ok(Convergence::Parallel),
+            "toe-in" | "toein" => Ok(Convergence::ToeIn),
+            _ => Err(format!("unknown convergence '{s}'"))
+        }
+    }
+}
+
+impl FromStr for StereoLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sbs" | "side-by-side" => Ok(StereoLayout::SideBySide),
+            "ou" | "over-under" => Ok(StereoLayout::OverUnder),
+            _ => Err(format!("unknown stereo layout '{s}'"))
+        }
+    }
+}
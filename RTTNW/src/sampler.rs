@@ -0,0 +1,428 @@
+// pixel sample generators. every random number the camera, lens and materials draw for one
+// pixel sample comes from a Sampler, so low-discrepancy sequences reach all the dimensions.
+// samples are fully determined by (seed, pixel, sample index, dimension).
+
+use std::str::FromStr;
+
+pub trait Sampler: Send + Sync {
+
+    // restart the sequence for sample `index` of pixel `px`
+    fn start_pixel_sample(&mut self, px: (u32, u32), index: u32);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+
+    fn clone_box(&self) -> Box<dyn Sampler>;
+
+}
+
+// stop sampling a pixel once the standard error of its mean luminance drops below
+// `threshold` times the mean, after at least min_spp and at most max_spp samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_spp: u32,
+    pub max_spp: u32,
+    pub threshold: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler '{s}'"))
+        }
+    }
+}
+
+impl SamplerKind {
+
+    pub fn build(&self, samples_per_px: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_px, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+
+}
+
+// plain uniform random numbers from a counter-based rng
+#[derive(Clone)]
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        IndependentSampler { seed, rng: Rng::new(seed) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+
+    fn start_pixel_sample(&mut self, px: (u32, u32), index: u32) {
+        self.rng = Rng::new(hash(&[self.seed, px.0 as u64, px.1 as u64, index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.next_f32(), self.rng.next_f32())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+}
+
+// jittered strata, with the stratum order shuffled independently per dimension
+#[derive(Clone)]
+pub struct StratifiedSampler {
+    samples_per_px: u32,
+    grid: (u32, u32),
+    seed: u64,
+
+    px: (u32, u32),
+    index: u32,
+    dim: u32,
+    rng: Rng,
+}
+
+impl StratifiedSampler {
+
+    pub fn new(samples_per_px: u32, seed: u64) -> Self {
+        let samples_per_px = samples_per_px.max(1);
+        let nx = (samples_per_px as f32).sqrt().ceil() as u32;
+        let ny = samples_per_px.div_ceil(nx);
+
+        StratifiedSampler {
+            samples_per_px,
+            grid: (nx, ny),
+            seed,
+            px: (0, 0),
+            index: 0,
+            dim: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    fn stratum(&self, count: u32) -> u32 {
+        // each block of count samples visits every one of the count strata once. a 2D grid
+        // can have a few more cells than samples_per_px, and the pixel's last, partial
+        // block then lands in a random subset of them
+        let round = self.index / count;
+        let key = hash(&[self.seed, self.px.0 as u64, self.px.1 as u64, self.dim as u64, round as u64]);
+
+        permute(self.index % count, count, key as u32)
+    }
+
+}
+
+impl Sampler for StratifiedSampler {
+
+    fn start_pixel_sample(&mut self, px: (u32, u32), index: u32) {
+        self.px = px;
+        self.index = index;
+        self.dim = 0;
+        self.rng = Rng::new(hash(&[self.seed, px.0 as u64, px.1 as u64, index as u64]));
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum(self.samples_per_px);
+        self.dim += 1;
+
+        ((stratum as f32 + self.rng.next_f32()) / self.samples_per_px as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (nx, ny) = self.grid;
+        let stratum = self.stratum(nx * ny);
+        self.dim += 2;
+
+        let x = (stratum % nx) as f32 + self.rng.next_f32();
+        let y = (stratum / nx) as f32 + self.rng.next_f32();
+
+        ((x / nx as f32).min(ONE_MINUS_EPSILON), (y / ny as f32).min(ONE_MINUS_EPSILON))
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+}
+
+// Owen-scrambled Halton sequence, one prime base per dimension
+#[derive(Clone)]
+pub struct HaltonSampler {
+    seed: u64,
+    px_seed: u64,
+    index: u32,
+    dim: u32,
+}
+
+const PRIMES: [u32; 48] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+];
+
+impl HaltonSampler {
+
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler { seed, px_seed: seed, index: 0, dim: 0 }
+    }
+
+    fn sample_dim(&mut self) -> f32 {
+        let dim = self.dim;
+        self.dim += 1;
+
+        let key = hash(&[self.px_seed, dim as u64]);
+
+        match PRIMES.get(dim as usize) {
+            Some(&base) => owen_radical_inverse(base, self.index, key),
+            // past the table the bases get large enough that the points are no better
+            // than random, so fall back to hashing
+            None => Rng::new(hash(&[key, self.index as u64])).next_f32()
+        }
+    }
+
+}
+
+impl Sampler for HaltonSampler {
+
+    fn start_pixel_sample(&mut self, px: (u32, u32), index: u32) {
+        self.px_seed = hash(&[self.seed, px.0 as u64, px.1 as u64]);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.sample_dim()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.sample_dim(), self.sample_dim())
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+}
+
+// Owen-scrambled Sobol (0,2)-sequence, padded across dimensions by shuffling the sample
+// index per dimension pair (Burley 2020, "Practical Hash-based Owen Scrambling")
+#[derive(Clone)]
+pub struct SobolSampler {
+    seed: u64,
+    px_seed: u64,
+    index: u32,
+    dim: u32,
+}
+
+impl SobolSampler {
+
+    pub fn new(seed: u64) -> Self {
+        SobolSampler { seed, px_seed: seed, index: 0, dim: 0 }
+    }
+
+    fn next_pair(&mut self) -> (f32, f32) {
+        let key = hash(&[self.px_seed, self.dim as u64]);
+        self.dim += 2;
+
+        let shuffled = nested_uniform_scramble(self.index, key as u32);
+
+        let x = nested_uniform_scramble(shuffled.reverse_bits(), (key >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_second_dim(shuffled), hash(&[key]) as u32);
+
+        (bits_to_f32(x), bits_to_f32(y))
+    }
+
+}
+
+impl Sampler for SobolSampler {
+
+    fn start_pixel_sample(&mut self, px: (u32, u32), index: u32) {
+        self.px_seed = hash(&[self.seed, px.0 as u64, px.1 as u64]);
+        self.index = index;
+        self.dim = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.next_pair().0
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        self.next_pair()
+    }
+
+    fn clone_box(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn bits_to_f32(bits: u32) -> f32 {
+    ((bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)).min(ONE_MINUS_EPSILON)
+}
+
+fn sobol_second_dim(mut index: u32) -> u32 {
+    // generator matrix of the second Sobol dimension, (x + 1), in bit-reversed order
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+
+    while index != 0 {
+        if index & 1 != 0 { result ^= v }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn owen_radical_inverse(base: u32, mut index: u32, seed: u64) -> f32 {
+    // radical inverse where every digit is permuted by a shift that depends on the digits
+    // before it. digits past the end of `index` are zeros that still get scrambled.
+
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut prefix = 0u64;
+    let mut result = 0.0;
+
+    while inv_base_m > 1e-9 {
+        let digit = index % base;
+        index /= base;
+
+        let shift = (hash(&[seed, prefix]) % base as u64) as u32;
+        let scrambled = (digit + shift) % base;
+
+        inv_base_m *= inv_base;
+        result += scrambled as f64 * inv_base_m;
+        prefix = prefix.wrapping_mul(base as u64).wrapping_add(digit as u64 + 1);
+    }
+
+    (result as f32).min(ONE_MINUS_EPSILON)
+}
+
+fn permute(mut i: u32, n: u32, seed: u32) -> u32 {
+    // random permutation of 0..n without a table (Kensler 2013, "Correlated Multi-Jittered Sampling")
+
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < n { break }
+    }
+
+    (i + seed) % n
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    // splitmix64 folded over the inputs
+
+    let mut h = 0x9e37_79b9_7f4a_7c15u64;
+
+    for &v in values {
+        h = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+
+    h
+}
+
+#[derive(Clone)]
+struct Rng {
+    state: u64
+}
+
+impl Rng {
+
+    fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        bits_to_f32((hash(&[self.state]) >> 32) as u32)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stratified_2d_visits_every_cell() {
+        // 32 spp is stratified on a 6x6 grid, which every block of 36 samples covers
+        let mut sampler = StratifiedSampler::new(32, 7);
+        let mut visited = [false; 36];
+
+        for index in 36..72 {
+            sampler.start_pixel_sample((3, 4), index);
+            let (x, y) = sampler.get_2d();
+            visited[(y * 6.0) as usize * 6 + (x * 6.0) as usize] = true;
+        }
+
+        assert!(visited.iter().all(|&v| v));
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::Mul;
+use std::str::FromStr;
+use std::sync::Arc;
+use itertools::iproduct;
+use rand::rngs::StdRng;
+use rand::{random, Rng, SeedableRng};
+use ultraviolet::{Rotor3, Vec3};
+use crate::animation::{Animated, CameraKey, CameraPath, Interpolation, Keyframe, Transform};
+use crate::aperture::ApertureShape;
+use crate::camera::{random_unit_vec, CameraSetup, Projection};
+use crate::debug::DebugShading;
+use crate::denoise::Denoiser;
+use crate::error::RenderError;
+use crate::film::Filter;
+use crate::hittable::{Hittable, HittableList};
+use crate::material::{Dielectric, Lambertian, Material, Metal};
+use crate::output::ExrPrecision;
+use crate::rig::Rig;
+use crate::sampler::SamplerKind;
+use crate::shutter::{Shutter, ShutterCurve};
+use crate::sky::{DaylightSky, Sky};
+use crate::sphere::{MovingSphere, Sphere};
+use crate::tonemap::ToneMap;
+
+// a camera and the objects it looks at, read from a scene description: one statement per
+// line, a keyword followed by its values, with # starting a comment. angles are in degrees.
+//
+//   image_height 240             spp 64                 look_from 13 2 3
+//   sky daylight 35 -60 3        material red lambertian 0.8 0.1 0.1
+//   sphere 0 1 0 1 red           moving_sphere 0 1 0  0 1.5 0  1 red
+//   projection fisheye 180       stereo sbs 0.065 toe-in  cube_map
+//   lens 50 1.4                  aperture polygon 6 90  cats_eye 0.5
+//   shutter 0 0.5 trapezoid      frame_shutter 12 24 180
+//   denoise                      denoise 8      (denoised, optionally at a given strength)
+//   debug normal                 (or uv, distance, front-face, material, bounces, cost)
+//   moving_sphere 0 1 0  0 1.5 0  1 red  0 0.5     (moving over times 0 to 0.5)
+//   final_scene                  (the book's cover scene, laid out from the seed)
+//
+// `animate linear|bezier` moves the object above it by the `key` lines after it, each
+// `key time x y z [rx ry rz [scale | sx sy sz]]`, turning by degrees about z, x then y:
+//
+//   sphere 0 0 0 1 red           animate bezier
+//   key 0  0 1 0                 key 1  2 1 0  0 90 0  0.5
+//
+// the camera can move too, by `camera_key time look_from look_at [vfov [focus_distance]]`
+// lines, Bezier unless `camera_path linear` says otherwise. it's placed where it is when
+// the shutter opens, and keys leaving out vfov or focus_distance keep the key before's
+//
+// anything not given falls back to the command line renderer's defaults. image_width
+// overrides aspect_ratio
+pub struct Scene {
+    pub camera: CameraSetup,
+    pub world: HittableList,
+}
+
+struct SceneSettings {
+    image_height: u32,
+    aspect_ratio: f32,
+    image_width: Option<u32>,
+    samples_per_px: u32,
+    max_depth: u32,
+    vfov: f32,
+    look_from: Vec3,
+    look_at: Vec3,
+    vertical_up: Vec3,
+    defocus_angle: f32,
+    focus_distance: f32,
+    lens: Option<(f32, f32)>,
+    aperture: ApertureShape,
+    cats_eye: f32,
+    shutter: Shutter,
+    camera_keys: Vec<CameraKey>,
+    camera_interpolation: Interpolation,
+    projection: Projection,
+    rig: Rig,
+    sky: Sky,
+    sampler: SamplerKind,
+    filter: Filter,
+    exr_precision: ExrPrecision,
+    exposure: f32,
+    tone_map: ToneMap,
+    adaptive: Option<(u32, u32, f32)>,
+    denoiser: Option<Denoiser>,
+    debug: Option<DebugShading>,
+    seed: Option<u64>,
+    final_scene: bool,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        SceneSettings {
+            image_height: 480,
+            aspect_ratio: 16.0 / 9.0,
+            image_width: None,
+            samples_per_px: 128,
+            max_depth: 32,
+            vfov: 20.0,
+            look_from: Vec3::new(13.0, 2.0, 3.0),
+            look_at: Vec3::new(0.0, 0.0, 0.0),
+            vertical_up: Vec3::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.6,
+            focus_distance: 10.0,
+            lens: None,
+            aperture: ApertureShape::Circle,
+            cats_eye: 0.0,
+            shutter: Shutter::default(),
+            camera_keys: Vec::new(),
+            camera_interpolation: Interpolation::Bezier,
+            projection: Projection::Perspective,
+            rig: Rig::Mono,
+            sky: Sky::Gradient,
+            sampler: SamplerKind::Independent,
+            filter: Filter::Box { radius: 0.5 },
+            exr_precision: ExrPrecision::Half,
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            adaptive: None,
+            denoiser: None,
+            debug: None,
+            seed: None,
+            final_scene: false,
+        }
+    }
+}
+
+pub fn parse_scene(text: &str) -> Result<Scene, RenderError> {
+
+    let mut settings = SceneSettings::default();
+    // by name, with the id each is numbered by in the material id aov
+    let mut materials: HashMap<String, (u32, Arc<dyn Material>)> = HashMap::new();
+    let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+    // the interpolation and keys of the last object, while its key lines are read
+    let mut animation: Option<(Interpolation, Vec<Keyframe>)> = None;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words[0] != "key" {
+            finish_animation(&mut animation, &mut objects);
+        }
+        parse_statement(words[0], &words[1..], &mut settings, &mut materials, &mut objects, &mut animation)
+            .map_err(|message| RenderError::Scene { line: n + 1, message })?;
+    }
+    finish_animation(&mut animation, &mut objects);
+
+    let s = settings;
+    let seed = s.seed.unwrap_or_else(random);
+
+    // the cover scene's materials are numbered after the ones declared
+    let mut world = if s.final_scene {
+        final_render_scene(seed, materials.len() as u32 + 1)
+    } else {
+        HittableList::new()
+    };
+    for object in objects {
+        world.add(object);
+    }
+
+    let camera = CameraSetup::default()
+    .with_image_height(s.image_height)
+    .with_aspect_ratio(s.aspect_ratio)
+    .with_samples_per_px(s.samples_per_px)
+    .with_max_depth(s.max_depth)
+    .with_vfov(s.vfov.to_radians())
+    .with_look_from(s.look_from)
+    .with_look_at(s.look_at)
+    .with_vertical_up(s.vertical_up)
+    .with_defocus_angle(s.defocus_angle.to_radians())
+    .with_focus_distance(s.focus_distance)
+    .with_aperture(s.aperture)
+    .with_cats_eye(s.cats_eye)
+    .with_shutter(s.shutter)
+    .with_projection(s.projection)
+    .with_rig(s.rig)
+    .with_sky(s.sky)
+    .with_sampler(s.sampler)
+    .with_filter(s.filter)
+    .with_exr_precision(s.exr_precision)
+    .with_exposure(s.exposure)
+    .with_tone_map(s.tone_map)
+    .with_seed(seed);
+
+    let camera = match s.lens {
+        Some((focal_length, f_number)) => camera.with_lens(focal_length, f_number),
+        None => camera
+    };
+
+    let camera = if s.camera_keys.is_empty() {
+        camera
+    } else {
+        let path = CameraPath::new(s.camera_keys, s.camera_interpolation);
+        camera.with_camera_key(&path.at(s.shutter.open))
+    };
+
+    let camera = match s.image_width {
+        Some(width) => camera.with_width(width),
+        None => camera
+    };
+
+    let camera = match s.adaptive {
+        Some((min_spp, max_spp, threshold)) => camera.with_adaptive_sampling(min_spp, max_spp, threshold),
+        None => camera
+    };
+
+    let camera = match s.denoiser {
+        Some(denoiser) => camera.with_denoiser(denoiser),
+        None => camera
+    };
+
+    let camera = match s.debug {
+        Some(mode) => camera.with_debug_shading(mode),
+        None => camera
+    };
+
+    Ok(Scene { camera, world })
+
+}
+
+fn parse_statement(
+    keyword: &str,
+    args: &[&str],
+    s: &mut SceneSettings,
+    materials: &mut HashMap<String, (u32, Arc<dyn Material>)>,
+    objects: &mut Vec<Box<dyn Hittable>>,
+    animation: &mut Option<(Interpolation, Vec<Keyframe>)>,
+) -> Result<(), String> {
+
+    let material = |name: &str| materials.get(name).cloned()
+        .ok_or_else(|| format!("unknown material '{name}'"));
+
+    match keyword {
+        "image_height" => s.image_height = value(args)?,
+        "aspect_ratio" => s.aspect_ratio = value(args)?,
+        "image_width" => s.image_width = Some(value(args)?),
+        "spp" => s.samples_per_px = value(args)?,
+        "max_depth" => s.max_depth = value(args)?,
+        "vfov" => s.vfov = value(args)?,
+        "look_from" => s.look_from = vec3(args)?,
+        "look_at" => s.look_at = vec3(args)?,
+        "up" => s.vertical_up = vec3(args)?,
+        "defocus_angle" => s.defocus_angle = value(args)?,
+        "focus_distance" => s.focus_distance = value(args)?,
+        "lens" => {
+            let [focal_length, f_number] = numbers::<2>(args)?;
+            s.lens = Some((focal_length, f_number));
+        }
+        "aperture" => s.aperture = args.join(":").parse()?,
+        "cats_eye" => s.cats_eye = value(args)?,
+        "shutter" => {
+            // open close [curve]
+            if !(2..=3).contains(&args.len()) { return Err("shutter needs open close [curve]".to_string()) }
+            let [open, close] = numbers::<2>(&args[..2])?;
+            let curve = args.get(2).map_or(Ok(ShutterCurve::Box), |c| c.parse())?;
+            s.shutter = Shutter { open, close, curve };
+        }
+        "frame_shutter" => {
+            // frame fps angle [curve], for a frame of an animation
+            if !(3..=4).contains(&args.len()) { return Err("frame_shutter needs frame fps angle [curve]".to_string()) }
+            let [fps, angle] = numbers::<2>(&args[1..3])?;
+            let curve = args.get(3).map_or(Ok(ShutterCurve::Box), |c| c.parse())?;
+            s.shutter = Shutter::from_frame(value(&args[..1])?, fps, angle.to_radians(), curve);
+        }
+        "camera_key" => {
+            let previous = s.camera_keys.last().copied().unwrap_or(CameraKey {
+                time: 0.0,
+                look_from: s.look_from,
+                look_at: s.look_at,
+                vfov: s.vfov.to_radians(),
+                focus_distance: s.focus_distance,
+            });
+            s.camera_keys.push(camera_key(args, &previous)?);
+        }
+        "camera_path" => s.camera_interpolation = value(args)?,
+        "projection" => s.projection = args.join(":").parse()?,
+        "stereo" => {
+            if args.len() != 3 { return Err("stereo needs sbs|ou eye_separation parallel|toe-in".to_string()) }
+            s.rig = Rig::Stereo {
+                layout: value(&args[0..1])?,
+                eye_separation: value(&args[1..2])?,
+                convergence: value(&args[2..3])?,
+            };
+        }
+        "cube_map" => s.rig = Rig::CubeMap,
+        "sampler" => s.sampler = value(args)?,
+        "filter" => s.filter = value(args)?,
+        "exr_precision" => s.exr_precision = value(args)?,
+        "exposure" => s.exposure = value(args)?,
+        "tonemap" => s.tone_map = value(args)?,
+        "seed" => s.seed = Some(value(args)?),
+        "adaptive" => {
+            if args.len() != 3 { return Err("adaptive needs min_spp max_spp threshold".to_string()) }
+            s.adaptive = Some((value(&args[0..1])?, value(&args[1..2])?, value(&args[2..3])?));
+        }
+        "denoise" => s.denoiser = Some(match args {
+            [] => Denoiser::default(),
+            _ => Denoiser { strength: value(args)?, ..Denoiser::default() },
+        }),
+        "debug" => s.debug = Some(value(args)?),
+        "sky" => s.sky = match args.first().copied() {
+            Some("gradient") => Sky::Gradient,
+            Some("daylight") => {
+                let [elevation, azimuth, turbidity] = numbers::<3>(&args[1..])?;
+                Sky::Daylight(DaylightSky::new(elevation.to_radians(), azimuth.to_radians(), turbidity))
+            }
+            _ => return Err("sky should be 'gradient' or 'daylight elevation azimuth turbidity'".to_string())
+        },
+        "material" => {
+            let [name, kind] = args.get(..2).and_then(|a| a.try_into().ok())
+                .ok_or("material needs a name and a type")?;
+
+            let mat: Arc<dyn Material> = match kind {
+                "lambertian" => Arc::new(Lambertian::new(vec3(&args[2..])?)),
+                "metal" => {
+                    let [r, g, b, fuzz] = numbers::<4>(&args[2..])?;
+                    Arc::new(Metal::new(Vec3::new(r, g, b), fuzz))
+                }
+                "dielectric" => Arc::new(Dielectric::new(value(&args[2..])?)),
+                _ => return Err(format!("unknown material type '{kind}'"))
+            };
+
+            // materials are numbered in the order they're declared. redeclaring one keeps
+            // its number
+            let id = materials.get(name).map_or(materials.len() as u32 + 1, |(id, _)| *id);
+            materials.insert(name.to_string(), (id, mat));
+        }
+        "sphere" => {
+            if args.len() != 5 { return Err("sphere needs x y z radius material".to_string()) }
+            let [x, y, z, radius] = numbers::<4>(&args[..4])?;
+            let (id, mat) = material(args[4])?;
+            objects.push(Box::new(Sphere::new(Vec3::new(x, y, z), radius, mat).with_material_id(id)));
+        }
+        "moving_sphere" => {
+            if args.len() != 8 && args.len() != 10 {
+                return Err("moving_sphere needs x0 y0 z0 x1 y1 z1 radius material [t0 t1]".to_string())
+            }
+            let [x0, y0, z0, x1, y1, z1, radius] = numbers::<7>(&args[..7])?;
+            let (id, mat) = material(args[7])?;
+            let sphere = MovingSphere::new(Vec3::new(x0, y0, z0), Vec3::new(x1, y1, z1), radius, mat)
+                .with_material_id(id);
+
+            objects.push(Box::new(match args.get(8..) {
+                Some(times) if !times.is_empty() => {
+                    let [t0, t1] = numbers::<2>(times)?;
+                    sphere.with_time_range(t0..t1)
+                }
+                _ => sphere,
+            }));
+        }
+        "animate" => {
+            if objects.is_empty() { return Err("animate needs an object before it".to_string()) }
+            *animation = Some((value(args)?, Vec::new()));
+        }
+        "key" => {
+            let Some((_, keys)) = animation else { return Err("key needs an animate before it".to_string()) };
+            if ![4, 7, 8, 10].contains(&args.len()) {
+                return Err("key needs time x y z [rx ry rz [scale | sx sy sz]]".to_string())
+            }
+            let time = value(&args[..1])?;
+            let translation = vec3(&args[1..4])?;
+            let rotation = match args.get(4..7) {
+                Some(angles) => {
+                    let [x, y, z] = numbers::<3>(angles)?;
+                    Rotor3::from_euler_angles(z.to_radians(), x.to_radians(), y.to_radians())
+                }
+                None => Rotor3::identity(),
+            };
+            let scale = match args.get(7..) {
+                Some([scale]) => Vec3::broadcast(value(&[scale])?),
+                Some(scale) if scale.len() == 3 => vec3(scale)?,
+                _ => Vec3::one(),
+            };
+            if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 { return Err("key scales can't be 0".to_string()) }
+
+            keys.push(Keyframe { time, transform: Transform { translation, rotation, scale } });
+        }
+        "final_scene" => s.final_scene = true,
+        _ => return Err(format!("unknown statement '{keyword}'"))
+    }
+
+    Ok(())
+
+}
+
+pub fn parse_camera_path(text: &str, interpolation: Interpolation, first: CameraKey) -> Result<CameraPath, RenderError> {
+    // a camera path file: a camera_key's values on each line, with # starting a comment.
+    // the first key takes any vfov or focus_distance it leaves out from `first`
+
+    let mut keys: Vec<CameraKey> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let key = camera_key(&words, keys.last().unwrap_or(&first))
+            .map_err(|message| RenderError::Scene { line: n + 1, message })?;
+        keys.push(key);
+    }
+
+    if keys.is_empty() { return Err(RenderError::InvalidConfig("the camera path has no keys".to_string())) }
+    Ok(CameraPath::new(keys, interpolation))
+}
+
+fn camera_key(args: &[&str], previous: &CameraKey) -> Result<CameraKey, String> {
+    // time look_from look_at [vfov [focus_distance]], vfov in degrees
+
+    if !(7..=9).contains(&args.len()) {
+        return Err("camera key needs time x y z x y z [vfov [focus_distance]]".to_string())
+    }
+
+    Ok(CameraKey {
+        time: value(&args[..1])?,
+        look_from: vec3(&args[1..4])?,
+        look_at: vec3(&args[4..7])?,
+        vfov: args.get(7).map_or(Ok(previous.vfov), |v| value::<f32>(&[v]).map(f32::to_radians))?,
+        focus_distance: args.get(8).map_or(Ok(previous.focus_distance), |v| value(&[v]))?,
+    })
+}
+
+fn finish_animation(animation: &mut Option<(Interpolation, Vec<Keyframe>)>, objects: &mut Vec<Box<dyn Hittable>>) {
+    // wraps the object being animated once its keys have all been read
+    if let Some((interpolation, keys)) = animation.take() && let Some(object) = objects.pop() {
+        objects.push(Box::new(Animated::new(object, keys, interpolation)));
+    }
+}
+
+fn values<const N: usize>(args: &[&str]) -> Result<[String; N], String> {
+    // exactly N words
+    if args.len() != N { return Err(format!("expected {N} values, found {}", args.len())) }
+    Ok(std::array::from_fn(|i| args[i].to_string()))
+}
+
+fn value<T: FromStr>(args: &[&str]) -> Result<T, String> where T::Err: Display {
+    let [v] = values::<1>(args)?;
+    v.parse().map_err(|e| format!("bad value '{v}': {e}"))
+}
+
+fn numbers<const N: usize>(args: &[&str]) -> Result<[f32; N], String> {
+    let words = values::<N>(args)?;
+    let mut out = [0.0; N];
+    for (o, w) in out.iter_mut().zip(&words) {
+        *o = w.parse().map_err(|_| format!("bad number '{w}'"))?;
+    }
+    Ok(out)
+}
+
+fn vec3(args: &[&str]) -> Result<Vec3, String> {
+    let [x, y, z] = numbers::<3>(args)?;
+    Ok(Vec3::new(x, y, z))
+}
+
+pub fn final_render_scene(seed: u64, first_material_id: u32) -> HittableList {
+    // setup for the final render scene, laid out the same way for the same seed. every
+    // sphere has a material of its own, numbered on from first_material_id
+    let mut scene = HittableList::new();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut material_ids = first_material_id..;
+    let mut next_id = || material_ids.next().unwrap();
+
+    let ground_mat = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+    scene.add(Box::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground_mat).with_material_id(next_id())));
+
+    for (a, b) in iproduct!(-11..11, -11..11) {
+
+        let choose_mat: f32 = rng.random();
+        let center = Vec3::new(
+            (a as f32) + 0.9 * rng.random::<f32>(),
+            0.2,
+            (b as f32) + 0.9 * rng.random::<f32>());
+
+        let sphere_material: Arc<dyn Material> = if choose_mat < 0.8 {
+            // diffuse/matte material
+            let colour = random_unit_vec(&mut rng).mul(random_unit_vec(&mut rng));
+            Arc::new(Lambertian::new(colour))
+
+        } else if choose_mat < 0.95 {
+            // metal
+            let colour = Vec3::new(
+                rng.random_range(0.5..1.0),
+                rng.random_range(0.5..1.0),
+                rng.random_range(0.5..1.0)
+            );
+            let fuzz = rng.random_range(0.0..0.5);
+            Arc::new(Metal::new(colour, fuzz))
+
+        } else {
+            // dielectric material
+            Arc::new(Dielectric::new(1.5))
+        };
+
+        if choose_mat < 0.8 {
+            let center_1 = center + Vec3::new(0.0, rng.random_range(0.0..0.5), 0.0);
+            scene.add(Box::new(MovingSphere::new(center, center_1, 0.2, sphere_material).with_material_id(next_id())))
+
+        } else {
+            scene.add(Box::new(Sphere::new(center, 0.2, sphere_material).with_material_id(next_id())));
+        }
+
+    }
+
+    let mat1 = Arc::new(Dielectric::new(1.5));
+    let mat2 = Arc::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1)));
+    let mat3 = Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
+
+    scene.add(Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, mat1).with_material_id(next_id())));
+    scene.add(Box::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0), 1.0, mat2).with_material_id(next_id())));
+    scene.add(Box::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0), 1.0, mat3).with_material_id(next_id())));
+
+    scene
+
+}
@@ -0,0 +1,102 @@
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use image::RgbImage;
+use crate::animation::CameraPath;
+use crate::camera::{Camera, CameraSetup};
+use crate::error::RenderError;
+use crate::output::{write_apng, write_gif};
+use crate::shutter::{Shutter, ShutterCurve};
+
+// the frames of an animation to render, first to last inclusive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+// a run of frames of an animation, each with the shutter open over its own part of the
+// timeline (frame / fps onwards), and the camera where its path has it when it opens
+pub struct Sequence {
+    pub frames: FrameRange,
+    pub fps: f32,
+    // of the 2 pi each frame lasts, in radians
+    pub shutter_angle: f32,
+    pub shutter_curve: ShutterCurve,
+    pub camera_path: Option<CameraPath>,
+    // dithering the frames of a gif to its palette
+    pub gif_dither: bool,
+}
+
+impl FrameRange {
+
+    pub fn frames(&self) -> RangeInclusive<u32> {
+        self.first..=self.last
+    }
+
+}
+
+impl Sequence {
+
+    pub fn frame_setup(&self, setup: &CameraSetup, frame: u32) -> CameraSetup {
+        // the camera for one frame
+
+        let shutter = Shutter::from_frame(frame, self.fps, self.shutter_angle, self.shutter_curve);
+        let setup = setup.clone().with_shutter(shutter);
+
+        match &self.camera_path {
+            Some(path) => setup.with_camera_key(&path.at(shutter.open)),
+            None => setup
+        }
+    }
+
+    pub fn is_animation(output: &str) -> bool {
+        // whether output is a single animated image (gif or apng) rather than a frame name
+        Path::new(output).extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("gif") || e.eq_ignore_ascii_case("apng"))
+    }
+
+    pub fn write_animation(&self, path: &Path, frames: &[RgbImage]) -> Result<(), RenderError> {
+        // every frame in one looping image, with the format following the extension
+
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => write_gif(path, frames, self.fps, self.gif_dither)?,
+            "apng" => write_apng(path, frames, self.fps)?,
+            _ => return Err(RenderError::InvalidConfig(format!("{} isn't a gif or apng", path.display()))),
+        }
+
+        println!("Saved {} frame animation to {}", frames.len(), path.display());
+        Ok(())
+    }
+
+    pub fn frame_path(output: &str, frame: u32) -> Result<PathBuf, RenderError> {
+        // output numbered for the frame, as name_0001.png, in the output directory
+
+        let output = Path::new(output);
+        let stem = output.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+        let name = match output.extension().and_then(|e| e.to_str()) {
+            Some(extension) => format!("{stem}_{frame:04}.{extension}"),
+            None => format!("{stem}_{frame:04}"),
+        };
+
+        Camera::output_path(&output.with_file_name(name).to_string_lossy())
+    }
+
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "first-last", or a single frame
+
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let frame = |f: &str| f.trim().parse::<u32>().map_err(|_| format!("bad frame '{f}' in frame range '{s}'"));
+        let (first, last) = (frame(first)?, frame(last)?);
+
+        if first > last { return Err(format!("frame range '{s}' ends before it starts")) }
+        Ok(FrameRange { first, last })
+    }
+}
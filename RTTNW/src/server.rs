@@ -0,0 +1,677 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use image::{ImageFormat, RgbImage};
+use crate::progress::{CancelToken, FrameBuffer, RenderObserver, RenderProgress, RenderSummary};
+use crate::scene::{parse_scene, Scene};
+
+// a small HTTP/1.1 render service, so tools can request renders without linking the crate.
+// scene descriptions (see scene.rs) are POSTed and rendered one at a time, in order:
+//
+//   POST   /renders?format=png|exr|hdr|pfm   queue a render, answering with its id
+//   GET    /renders                          the status of every render
+//   GET    /renders/{id}                     the status and progress of one render
+//   GET    /renders/{id}/progress            server-sent status events until it ends
+//   GET    /renders/{id}/preview             the image as of the last finished pass, as png
+//   GET    /renders/{id}/result              the finished image
+//   DELETE /renders/{id}                     cancel a render, or forget a finished one
+
+// how often progress streams send an event
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+// how long a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// largest scene description accepted, in bytes
+const MAX_BODY: usize = 1 << 20;
+// largest renders accepted, so one request can't take all the memory or the rest of time
+const MAX_PIXELS: u64 = 1 << 24;
+const MAX_SPP: u32 = 1 << 16;
+
+#[derive(Clone)]
+enum Status {
+    Queued,
+    Rendering,
+    Done,
+    Cancelled,
+    Failed(String),
+}
+
+impl Status {
+
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Rendering => "rendering",
+            Status::Done => "done",
+            Status::Cancelled => "cancelled",
+            Status::Failed(_) => "failed",
+        }
+    }
+
+    fn finished(&self) -> bool {
+        !matches!(self, Status::Queued | Status::Rendering)
+    }
+
+}
+
+struct RenderEntry {
+    id: u64,
+    // output file extension, which picks the format
+    format: &'static str,
+    // taken by the worker when the render starts
+    scene: Mutex<Option<Scene>>,
+    status: Mutex<Status>,
+    // as last reported by the camera
+    progress: Mutex<Option<RenderProgress>>,
+    preview: Mutex<Option<RgbImage>>,
+    cancel: CancelToken,
+    result: Mutex<Option<Arc<Vec<u8>>>>,
+}
+
+impl RenderEntry {
+
+    fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn to_json(&self) -> String {
+        let status = self.status();
+
+        let latest = self.progress.lock().unwrap().clone();
+
+        let fraction = match status {
+            Status::Done => 1.0,
+            _ => latest.as_ref().map_or(0.0, |p| p.fraction),
+        };
+
+        let mut json = format!("{{\"id\": {}, \"status\": \"{}\", \"progress\": {fraction:.4}", self.id, status.name());
+
+        if let Some(p) = latest {
+            let seconds = |d: Option<Duration>| d.map_or("null".to_string(), |d| format!("{:.1}", d.as_secs_f32()));
+            json += &format!(", \"pass\": {}, \"passes\": {}, \"tiles_done\": {}, \"tiles_total\": {}, \
+                              \"elapsed\": {}, \"eta\": {}",
+                p.pass, p.passes, p.tiles_done, p.tiles_total.map_or("null".to_string(), |t| t.to_string()),
+                seconds(Some(p.elapsed)), seconds(p.eta));
+        }
+        if let Status::Failed(e) = &status {
+            json += &format!(", \"error\": {}", json_string(e));
+        }
+
+        json + "}"
+    }
+
+}
+
+impl RenderObserver for RenderEntry {
+
+    fn on_progress(&self, progress: &RenderProgress) {
+        *self.progress.lock().unwrap() = Some(progress.clone());
+    }
+
+    fn on_pass(&self, progress: &RenderProgress, frame: &FrameBuffer) {
+        self.on_progress(progress);
+        *self.preview.lock().unwrap() = Some(frame.to_image());
+    }
+
+    fn on_finish(&self, summary: &RenderSummary) {
+        let verb = if summary.cancelled { "stopped after" } else { "rendered in" };
+        println!("Request {} {verb} {:.1}s, {:.1} samples per pixel",
+            self.id, summary.elapsed.as_secs_f32(), summary.mean_spp);
+    }
+
+}
+
+struct Service {
+    renders: Mutex<BTreeMap<u64, Arc<RenderEntry>>>,
+    queue: Mutex<VecDeque<Arc<RenderEntry>>>,
+    queued: Condvar,
+    next_id: AtomicU64,
+    // set to stop the worker and the listener
+    stopping: AtomicBool,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+pub fn serve(addr: &str) -> io::Result<()> {
+    let server = Server::bind(addr)?;
+    println!("Render service listening on http://{}", server.local_addr()?);
+    server.run()
+}
+
+// the service bound to its address, but not yet answering
+pub struct Server {
+    listener: TcpListener,
+    service: Arc<Service>,
+}
+
+impl Server {
+
+    pub fn bind(addr: &str) -> io::Result<Self> {
+
+        let listener = TcpListener::bind(addr)?;
+
+        let service = Arc::new(Service {
+            renders: Mutex::new(BTreeMap::new()),
+            queue: Mutex::new(VecDeque::new()),
+            queued: Condvar::new(),
+            next_id: AtomicU64::new(1),
+            stopping: AtomicBool::new(false),
+        });
+
+        Ok(Server { listener, service })
+
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        // the port the OS picked, when bound to port 0
+        self.listener.local_addr()
+    }
+
+    pub fn run(&self) -> io::Result<()> {
+        // answers requests until shut down
+
+        // a single worker, as every render already uses all the cores
+        let worker = self.service.clone();
+        let worker = thread::spawn(move || worker.run_queue());
+
+        for stream in self.listener.incoming() {
+            if self.service.stopping.load(Ordering::Relaxed) { break }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => { println!("Error accepting connection: {e}"); continue }
+            };
+
+            // progress streams stay open for a whole render, so each connection gets a thread
+            let service = self.service.clone();
+            thread::spawn(move || {
+                service.handle(stream).unwrap_or_else(|e| println!("Error handling request: {e}"));
+            });
+        }
+
+        worker.join().map_err(|_| io::Error::other("render worker panicked"))
+
+    }
+
+    #[cfg(test)]
+    pub fn shutdown(&self) -> io::Result<()> {
+        // cancels every render and stops run() once the one in progress has stopped
+
+        {
+            // set while holding the queue, so the worker can't miss it between checking
+            // and waiting
+            let _queue = self.service.queue.lock().unwrap();
+            self.service.stopping.store(true, Ordering::Relaxed);
+            self.service.queued.notify_all();
+        }
+        for entry in self.service.renders.lock().unwrap().values() {
+            entry.cancel.cancel();
+        }
+
+        // the listener only checks between connections, so make one
+        TcpStream::connect(self.local_addr()?).map(drop)
+
+    }
+
+}
+
+impl Service {
+
+    fn run_queue(&self) {
+
+        loop {
+            let entry = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    if self.stopping.load(Ordering::Relaxed) { return }
+                    if let Some(entry) = queue.pop_front() { break entry }
+                    queue = self.queued.wait(queue).unwrap();
+                }
+            };
+
+            // renders cancelled while they were queued are just dropped
+            {
+                let mut status = entry.status.lock().unwrap();
+                if !matches!(*status, Status::Queued) { continue }
+                *status = Status::Rendering;
+            }
+            let Some(scene) = entry.scene.lock().unwrap().take() else { continue };
+
+            // a render that panics fails on its own, leaving the service running
+            println!("Rendering request {}", entry.id);
+            let status = panic::catch_unwind(AssertUnwindSafe(|| render(&entry, scene)))
+                .unwrap_or_else(|_| Status::Failed("the renderer crashed".to_string()));
+            println!("Request {} {}", entry.id, status.name());
+
+            *entry.status.lock().unwrap() = status;
+        }
+
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let request = match read_request(&stream) {
+            Ok(request) => request,
+            Err(e) => return respond_error(&mut stream, "400 Bad Request", &e.to_string()),
+        };
+
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["renders"]) => self.submit(&mut stream, &request),
+            ("GET", ["renders"]) => {
+                let renders: Vec<String> = self.renders.lock().unwrap().values().map(|e| e.to_json()).collect();
+                respond_json(&mut stream, "200 OK", &format!("[{}]", renders.join(", ")))
+            }
+            ("GET", ["renders", id, rest @ ..]) => {
+                let Some(entry) = self.entry(id) else {
+                    return respond_error(&mut stream, "404 Not Found", "no such render");
+                };
+
+                match rest {
+                    [] => respond_json(&mut stream, "200 OK", &entry.to_json()),
+                    ["progress"] => stream_progress(&mut stream, &entry),
+                    ["preview"] => send_preview(&mut stream, &entry),
+                    ["result"] => send_result(&mut stream, &entry),
+                    _ => respond_error(&mut stream, "404 Not Found", "unknown endpoint"),
+                }
+            }
+            ("DELETE", ["renders", id]) => {
+                let Some(entry) = self.entry(id) else {
+                    return respond_error(&mut stream, "404 Not Found", "no such render");
+                };
+                self.cancel(&mut stream, &entry)
+            }
+            _ => respond_error(&mut stream, "404 Not Found", "unknown endpoint"),
+        }
+
+    }
+
+    fn submit(&self, stream: &mut TcpStream, request: &Request) -> io::Result<()> {
+
+        let format = query_value(&request.query, "format").unwrap_or("png");
+        let Some(format) = ["png", "exr", "hdr", "pfm"].into_iter().find(|f| f.eq_ignore_ascii_case(format)) else {
+            return respond_error(stream, "400 Bad Request", &format!("unknown format '{format}'"));
+        };
+
+        let Ok(text) = std::str::from_utf8(&request.body) else {
+            return respond_error(stream, "400 Bad Request", "scene description isn't utf-8");
+        };
+        let scene = match parse_scene(text) {
+            Ok(scene) => scene,
+            Err(e) => return respond_error(stream, "400 Bad Request", &format!("invalid scene: {e}")),
+        };
+        let pixels = scene.camera.frame_size().map_or(u64::MAX, |(width, height)| width as u64 * height as u64);
+        if pixels > MAX_PIXELS {
+            return respond_error(stream, "400 Bad Request", &format!("the frame has more than {MAX_PIXELS} pixels"));
+        }
+        if scene.camera.max_samples_per_px() > MAX_SPP {
+            return respond_error(stream, "400 Bad Request", &format!("more than {MAX_SPP} samples per pixel"));
+        }
+
+        let entry = Arc::new(RenderEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            format,
+            scene: Mutex::new(Some(scene)),
+            status: Mutex::new(Status::Queued),
+            progress: Mutex::new(None),
+            preview: Mutex::new(None),
+            cancel: CancelToken::new(),
+            result: Mutex::new(None),
+        });
+
+        self.renders.lock().unwrap().insert(entry.id, entry.clone());
+        self.queue.lock().unwrap().push_back(entry.clone());
+        self.queued.notify_one();
+
+        respond_json(stream, "202 Accepted", &entry.to_json())
+
+    }
+
+    fn cancel(&self, stream: &mut TcpStream, entry: &RenderEntry) -> io::Result<()> {
+
+        let mut status = entry.status.lock().unwrap();
+
+        match *status {
+            Status::Queued => {
+                entry.cancel.cancel();
+                *status = Status::Cancelled;
+                drop(status);
+                respond_json(stream, "200 OK", &entry.to_json())
+            }
+            Status::Rendering => {
+                // the worker marks it cancelled once the camera stops
+                entry.cancel.cancel();
+                drop(status);
+                respond_json(stream, "202 Accepted", &entry.to_json())
+            }
+            _ => {
+                drop(status);
+                self.renders.lock().unwrap().remove(&entry.id);
+                respond_json(stream, "200 OK", &format!("{{\"id\": {}, \"status\": \"removed\"}}", entry.id))
+            }
+        }
+
+    }
+
+    fn entry(&self, id: &str) -> Option<Arc<RenderEntry>> {
+        let id: u64 = id.parse().ok()?;
+        self.renders.lock().unwrap().get(&id).cloned()
+    }
+
+}
+
+fn render(entry: &Arc<RenderEntry>, scene: Scene) -> Status {
+
+    let camera_setup = scene.camera
+        .with_observer(entry.clone())
+        .with_cancel_token(entry.cancel.clone());
+
+    let mut camera = match camera_setup.build() {
+        Ok(camera) => camera,
+        Err(e) => return Status::Failed(format!("invalid camera setup: {e}")),
+    };
+    if let Err(e) = camera.render(&scene.world) {
+        return Status::Failed(format!("render failed: {e}"));
+    }
+
+    if entry.cancel.is_cancelled() { return Status::Cancelled }
+
+    // the camera's own writers produce the image, which is then read back
+    let path = std::env::temp_dir()
+        .join(format!("rttnw-render-{}-{}.{}", std::process::id(), entry.id, entry.format));
+    let bytes = camera.save_to(&path).and_then(|_| Ok(fs::read(&path)?));
+    fs::remove_file(&path).ok();
+
+    match bytes {
+        Ok(bytes) => {
+            *entry.result.lock().unwrap() = Some(Arc::new(bytes));
+            Status::Done
+        }
+        Err(e) => Status::Failed(format!("couldn't write the image: {e}")),
+    }
+
+}
+
+fn read_request(stream: &TcpStream) -> io::Result<Request> {
+
+    let mut reader = BufReader::new(stream);
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+
+    // only the body length matters out of the headers
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 { break }
+
+        let header = header.trim_end();
+        if header.is_empty() { break }
+
+        if let Some((name, value)) = header.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().map_err(|_| invalid("bad content length"))?;
+        }
+    }
+
+    if content_length > MAX_BODY { return Err(invalid("request body too large")) }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    Ok(Request {
+        method: method.to_ascii_uppercase(),
+        path: path.to_string(),
+        query: query.to_string(),
+        body,
+    })
+
+}
+
+fn stream_progress(stream: &mut TcpStream, entry: &RenderEntry) -> io::Result<()> {
+    // server-sent events with the render's status until it finishes
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+                    Connection: close\r\n\r\n")?;
+
+    loop {
+        let finished = entry.status().finished();
+
+        write!(stream, "data: {}\n\n", entry.to_json())?;
+        stream.flush()?;
+
+        if finished { return Ok(()) }
+        thread::sleep(PROGRESS_INTERVAL);
+    }
+}
+
+fn send_result(stream: &mut TcpStream, entry: &RenderEntry) -> io::Result<()> {
+
+    let Some(bytes) = entry.result.lock().unwrap().clone() else {
+        let msg = format!("render is {}", entry.status().name());
+        return respond_error(stream, "409 Conflict", &msg);
+    };
+
+    let content_type = match entry.format {
+        "png" => "image/png",
+        "exr" => "image/x-exr",
+        "hdr" => "image/vnd.radiance",
+        _ => "application/octet-stream",
+    };
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+                    Content-Disposition: attachment; filename=\"render-{}.{}\"\r\nConnection: close\r\n\r\n",
+        bytes.len(), entry.id, entry.format)?;
+    stream.write_all(&bytes)?;
+    stream.flush()
+
+}
+
+fn send_preview(stream: &mut TcpStream, entry: &RenderEntry) -> io::Result<()> {
+
+    let mut png = Cursor::new(Vec::new());
+    match &*entry.preview.lock().unwrap() {
+        Some(image) => image.write_to(&mut png, ImageFormat::Png)
+            .map_err(io::Error::other)?,
+        None => return respond_error(stream, "409 Conflict", "no pass has finished yet"),
+    }
+    let png = png.into_inner();
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\
+                    Cache-Control: no-cache\r\nConnection: close\r\n\r\n", png.len())?;
+    stream.write_all(&png)?;
+    stream.flush()
+
+}
+
+fn respond_json(stream: &mut TcpStream, status: &str, json: &str) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n{json}", json.len() + 1)?;
+    writeln!(stream)?;
+    stream.flush()
+}
+
+fn respond_error(stream: &mut TcpStream, status: &str, msg: &str) -> io::Result<()> {
+    respond_json(stream, status, &format!("{{\"error\": {}}}", json_string(msg)))
+}
+
+fn query_value<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TINY_SCENE: &str = "image_height 8\nimage_width 8\nspp 2\nmaterial red lambertian 0.8 0.1 0.1\n\
+                              sphere 0 0 -1 0.5 red\n";
+    // many small tiles, so a cancelled render stops soon after it's asked to
+    const SLOW_SCENE: &str = "image_height 512\nimage_width 512\nspp 256\nmaterial red lambertian 0.8 0.1 0.1\n\
+                              sphere 0 0 -1 0.5 red\n";
+
+    fn start() -> (Arc<Server>, SocketAddr, thread::JoinHandle<io::Result<()>>) {
+        let server = Arc::new(Server::bind("127.0.0.1:0").unwrap());
+        let addr = server.local_addr().unwrap();
+        let runner = server.clone();
+        (server, addr, thread::spawn(move || runner.run()))
+    }
+
+    fn stop(server: &Server, handle: thread::JoinHandle<io::Result<()>>) {
+        server.shutdown().unwrap();
+        handle.join().unwrap().unwrap();
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u32, Vec<u8>) {
+        // the status code and body of the response
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\n\r\n{body}", body.len())
+            .unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = std::str::from_utf8(&response[..head_end]).unwrap();
+        let code = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (code, response[head_end + 4..].to_vec())
+    }
+
+    fn json(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u32, String) {
+        let (code, body) = request(addr, method, path, body);
+        (code, String::from_utf8(body).unwrap())
+    }
+
+    fn field<'a>(json: &'a str, key: &str) -> &'a str {
+        // the raw value of a top level field, quotes and all for strings
+        let start = json.find(&format!("\"{key}\": ")).unwrap() + key.len() + 4;
+        let len = json[start..].find([',', '}']).unwrap();
+        &json[start..start + len]
+    }
+
+    fn wait_for(addr: SocketAddr, id: &str, status: &str) {
+        for _ in 0..1200 {
+            let (_, render) = json(addr, "GET", &format!("/renders/{id}"), "");
+            if field(&render, "status") == format!("\"{status}\"") { return }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("render {id} never became {status}");
+    }
+
+    #[test]
+    fn renders_a_posted_scene() {
+        let (server, addr, handle) = start();
+
+        let (code, queued) = json(addr, "POST", "/renders?format=png", TINY_SCENE);
+        assert_eq!(code, 202);
+        let id = field(&queued, "id").to_string();
+
+        wait_for(addr, &id, "done");
+
+        let (code, png) = request(addr, "GET", &format!("/renders/{id}/result"), "");
+        assert_eq!(code, 200);
+        let image = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 8));
+
+        // deleting a finished render forgets it
+        let (code, removed) = json(addr, "DELETE", &format!("/renders/{id}"), "");
+        assert_eq!((code, field(&removed, "status")), (200, "\"removed\""));
+        assert_eq!(json(addr, "GET", &format!("/renders/{id}"), "").0, 404);
+
+        stop(&server, handle);
+    }
+
+    #[test]
+    fn cancels_queued_and_running_renders() {
+        let (server, addr, handle) = start();
+
+        let running = field(&json(addr, "POST", "/renders", SLOW_SCENE).1, "id").to_string();
+        wait_for(addr, &running, "rendering");
+        let queued = field(&json(addr, "POST", "/renders", TINY_SCENE).1, "id").to_string();
+
+        // a queued render is dropped there and then
+        let (code, render) = json(addr, "DELETE", &format!("/renders/{queued}"), "");
+        assert_eq!((code, field(&render, "status")), (200, "\"cancelled\""));
+
+        // a running one stops after the tiles in flight
+        assert_eq!(json(addr, "DELETE", &format!("/renders/{running}"), "").0, 202);
+        wait_for(addr, &running, "cancelled");
+
+        assert_eq!(json(addr, "GET", &format!("/renders/{running}/result"), "").0, 409);
+        assert_eq!(json(addr, "GET", &format!("/renders/{queued}/result"), "").0, 409);
+
+        stop(&server, handle);
+    }
+
+    #[test]
+    fn fails_renders_it_cant_set_up_and_carries_on() {
+        let (server, addr, handle) = start();
+
+        let failed = field(&json(addr, "POST", "/renders", "vfov 200\n").1, "id").to_string();
+        wait_for(addr, &failed, "failed");
+        let done = field(&json(addr, "POST", "/renders", TINY_SCENE).1, "id").to_string();
+        wait_for(addr, &done, "done");
+
+        stop(&server, handle);
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let (server, addr, handle) = start();
+
+        assert_eq!(json(addr, "POST", "/renders?format=gif", TINY_SCENE).0, 400);
+        assert_eq!(json(addr, "POST", "/renders", "sphere 0 0 -1 0.5 nowhere\n").0, 400);
+        assert_eq!(json(addr, "POST", "/renders", "image_height 100000\nimage_width 100000\nspp 1\n").0, 400);
+        let stereo = "image_height 3000\nimage_width 3000\nstereo sbs 0.065 parallel\n";
+        assert_eq!(json(addr, "POST", "/renders", stereo).0, 400);
+        assert_eq!(json(addr, "POST", "/renders", "image_height 8\nspp 1000000\n").0, 400);
+        assert_eq!(json(addr, "GET", "/renders/99", "").0, 404);
+        assert_eq!(json(addr, "GET", "/renders/99/result", "").0, 404);
+        assert_eq!(json(addr, "DELETE", "/renders/99", "").0, 404);
+        assert_eq!(json(addr, "GET", "/scenes", "").0, 404);
+
+        // nothing was queued
+        assert_eq!(json(addr, "GET", "/renders", "").1.trim(), "[]");
+
+        stop(&server, handle);
+    }
+}
@@ -0,0 +1,97 @@
+use std::str::FromStr;
+
+// when the shutter lets light in, in the same time units objects move in. the default
+// opens at 0 and closes at 1, fully open throughout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+    pub curve: ShutterCurve,
+}
+
+// how far open the shutter is over the interval, which weights the times rays are sent at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShutterCurve {
+    // fully open from open to close
+    Box,
+    // opening linearly over the first `ramp` of the interval and closing over the last,
+    // ramp from 0 to 0.5
+    Trapezoid { ramp: f32 },
+}
+
+impl Shutter {
+
+    pub fn from_frame(frame: u32, fps: f32, angle: f32, curve: ShutterCurve) -> Self {
+        // the shutter of a rotary film camera: open from the start of the frame for angle
+        // (radians) of the 2 pi a frame lasts. angle = pi is the usual 180 degree shutter
+        let open = frame as f32 / fps;
+        Shutter { open, close: open + angle / (2.0 * std::f32::consts::PI) / fps, curve }
+    }
+
+    pub fn sample(&self, u: f32) -> f32 {
+        // maps a uniform sample to a time, spread as the shutter curve lets light in
+
+        let x = match self.curve {
+            ShutterCurve::Box => u,
+            ShutterCurve::Trapezoid { ramp } if ramp > 0.0 => {
+                // inverts the area under the curve, which ramps 0 -> 1 -> 0 at unit height
+                let area = 1.0 - ramp;
+                let a = u * area;
+
+                if a < ramp / 2.0 {
+                    (2.0 * ramp * a).sqrt()
+                } else if a <= area - ramp / 2.0 {
+                    a + ramp / 2.0
+                } else {
+                    1.0 - (2.0 * ramp * (area - a)).max(0.0).sqrt()
+                }
+            }
+            ShutterCurve::Trapezoid { .. } => u,
+        };
+
+        self.open + x * (self.close - self.open)
+    }
+
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Shutter { open: 0.0, close: 1.0, curve: ShutterCurve::Box }
+    }
+}
+
+impl FromStr for Shutter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "open,close", fully open in between
+
+        let Some((open, close)) = s.split_once(',') else {
+            return Err(format!("shutter '{s}' should be open,close"));
+        };
+        let open: f32 = open.trim().parse().map_err(|_| format!("bad opening time in shutter '{s}'"))?;
+        let close: f32 = close.trim().parse().map_err(|_| format!("bad closing time in shutter '{s}'"))?;
+
+        Ok(Shutter { open, close, curve: ShutterCurve::Box })
+    }
+}
+
+impl FromStr for ShutterCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "box", or "trapezoid" with an optional ":ramp" (default 0.25)
+
+        let (name, ramp) = s.split_once(':').unwrap_or((s, "0.25"));
+
+        match name.trim().to_ascii_lowercase().as_str() {
+            "box" if !s.contains(':') => Ok(ShutterCurve::Box),
+            "trapezoid" => {
+                let ramp: f32 = ramp.trim().parse().map_err(|_| format!("bad ramp in shutter curve '{s}'"))?;
+                if !(0.0..=0.5).contains(&ramp) { return Err(format!("shutter curve '{s}' needs a ramp in 0..0.5")) }
+                Ok(ShutterCurve::Trapezoid { ramp })
+            }
+            _ => Err(format!("unknown shutter curve '{s}'"))
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+use ultraviolet::Vec3;
+
+#[derive(Clone, Debug)]
+pub enum Sky {
+    // the blue-white lerp from the book
+    Gradient,
+    // Preetham et al. analytic daylight with a sampled sun disc
+    Daylight(DaylightSky),
+}
+
+impl Sky {
+
+    pub fn radiance(&self, direction: Vec3, include_sun: bool) -> Vec3 {
+        // radiance arriving along the (reversed) direction of an escaped ray
+
+        match self {
+            Sky::Gradient => {
+                let unit_dir = direction.normalized();
+                let a = 0.5 * (unit_dir.y + 1.0);
+
+                (1.0 - a) * Vec3::one() + a * Vec3::new(0.5, 0.7, 1.0)
+            }
+            Sky::Daylight(sky) => sky.radiance(direction.normalized(), include_sun)
+        }
+
+    }
+
+    pub fn sample_sun(&self, u: (f32, f32)) -> Option<SunSample> {
+        match self {
+            Sky::Gradient => None,
+            Sky::Daylight(sky) => Some(sky.sample_sun(u))
+        }
+    }
+
+}
+
+impl FromStr for Sky {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // "gradient", or "daylight" with an optional ":elevation,azimuth,turbidity", the
+        // angles in degrees (default 35,-60,3)
+
+        let (name, values) = match s.split_once(':') {
+            Some((name, values)) => (name, Some(values)),
+            None => (s, None),
+        };
+
+        match (name.trim().to_ascii_lowercase().as_str(), values) {
+            ("gradient", None) => Ok(Sky::Gradient),
+            ("daylight", None) => "daylight:35,-60,3".parse(),
+            ("daylight", Some(values)) => {
+                let values: Vec<f32> = values.split(',')
+                    .map(|v| v.trim().parse().map_err(|_| format!("bad value in sky '{s}'")))
+                    .collect::<Result<_, _>>()?;
+                let [elevation, azimuth, turbidity] = values[..] else {
+                    return Err(format!("sky '{s}' should be daylight:elevation,azimuth,turbidity"));
+                };
+
+                Ok(Sky::Daylight(DaylightSky::new(elevation.to_radians(), azimuth.to_radians(), turbidity)))
+            }
+            _ => Err(format!("unknown sky '{s}'"))
+        }
+    }
+}
+
+pub struct SunSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    // 1 / pdf of the uniform cone sample
+    pub solid_angle: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct DaylightSky {
+    sun_dir: Vec3,
+    theta_sun: f32,
+    zenith: Vec3,        // zenith Y, x, y
+    perez_lum: [f32; 5],
+    perez_x: [f32; 5],
+    perez_y: [f32; 5],
+
+    sun_cos_max: f32,
+    sun_radiance: Vec3,
+    sun_u: Vec3,
+    sun_v: Vec3,
+}
+
+// mean angular radius of the sun seen from earth
+const SUN_ANGULAR_RADIUS: f32 = 0.004_65;
+// luminance of the sun disc outside the atmosphere in kcd/m^2
+const SUN_LUMINANCE: f32 = 1.6e6;
+// scale from kcd/m^2 to render units, leaving headroom for the sun-lit surfaces
+const SKY_SCALE: f32 = 1.0 / 25.0;
+
+impl DaylightSky {
+
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        // elevation above the horizon and azimuth from -z towards +x, both in radians.
+        // turbidity is clamped to the 2..10 range the model was fitted for.
+
+        let t = turbidity.clamp(2.0, 10.0);
+        let elevation = elevation.clamp(0.0, PI / 2.0);
+        let theta_sun = PI / 2.0 - elevation;
+
+        let sun_dir = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos()
+        ).normalized();
+
+        let perez_lum = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        // zenith luminance (kcd/m^2) and chromaticity
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_lum = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+        let (th, th2, th3) = (theta_sun, theta_sun.powi(2), theta_sun.powi(3));
+        let zenith_x =
+            t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y =
+            t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        // orthonormal basis around the sun for cone sampling
+        let helper = if sun_dir.x.abs() > 0.9 { Vec3::unit_y() } else { Vec3::unit_x() };
+        let sun_u = helper.cross(sun_dir).normalized();
+        let sun_v = sun_dir.cross(sun_u);
+
+        DaylightSky {
+            sun_dir,
+            theta_sun,
+            zenith: Vec3::new(zenith_lum.max(0.0), zenith_x, zenith_y),
+            perez_lum,
+            perez_x,
+            perez_y,
+
+            sun_cos_max: SUN_ANGULAR_RADIUS.cos(),
+            sun_radiance: SUN_LUMINANCE * sun_transmittance(theta_sun, t),
+            sun_u,
+            sun_v,
+        }
+
+    }
+
+    fn radiance(&self, dir: Vec3, include_sun: bool) -> Vec3 {
+
+        let cos_gamma = dir.dot(self.sun_dir).clamp(-1.0, 1.0);
+
+        if include_sun && cos_gamma >= self.sun_cos_max {
+            return SKY_SCALE * self.sun_radiance;
+        }
+
+        // the model is only defined above the horizon, so hold the horizon value below it
+        let cos_theta = dir.y.max(0.01);
+        let gamma = cos_gamma.acos();
+
+        let lum = self.zenith.x * self.perez_ratio(&self.perez_lum, cos_theta, gamma);
+        let x = self.zenith.y * self.perez_ratio(&self.perez_x, cos_theta, gamma);
+        let y = self.zenith.z * self.perez_ratio(&self.perez_y, cos_theta, gamma);
+
+        SKY_SCALE * xyy_to_rgb(x, y, lum)
+
+    }
+
+    fn perez_ratio(&self, coeffs: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+        // F(theta, gamma) / F(0, theta_sun)
+        perez(coeffs, cos_theta, gamma) / perez(coeffs, 1.0, self.theta_sun)
+    }
+
+    fn sample_sun(&self, u: (f32, f32)) -> SunSample {
+        // uniform sample over the cone subtended by the sun disc
+
+        let cos_theta = 1.0 - u.0 * (1.0 - self.sun_cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u.1;
+
+        let direction = (self.sun_u * (phi.cos() * sin_theta)
+            + self.sun_v * (phi.sin() * sin_theta)
+            + self.sun_dir * cos_theta).normalized();
+
+        SunSample {
+            direction,
+            radiance: SKY_SCALE * self.sun_radiance,
+            solid_angle: 2.0 * PI * (1.0 - self.sun_cos_max),
+        }
+
+    }
+
+}
+
+fn perez(coeffs: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coeffs;
+    let cos_gamma = gamma.cos();
+
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+fn xyy_to_rgb(x: f32, y: f32, lum: f32) -> Vec3 {
+    // CIE xyY -> XYZ -> linear sRGB (D65)
+
+    if y <= 0.0 { return Vec3::zero() }
+
+    let cx = x * lum / y;
+    let cz = (1.0 - x - y) * lum / y;
+
+    Vec3::new(
+        3.2406 * cx - 1.5372 * lum - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * lum + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * lum + 1.0570 * cz,
+    ).max_by_component(Vec3::zero())
+}
+
+fn sun_transmittance(theta_sun: f32, turbidity: f32) -> Vec3 {
+    // Rayleigh and aerosol attenuation of sunlight from the Preetham paper's appendix,
+    // evaluated at a representative wavelength (in micrometres) for each channel
+
+    let theta_deg = theta_sun.to_degrees();
+    let mass = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_deg).max(1e-3).powf(-1.253));
+
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+
+    let channel = |lambda: f32| {
+        let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+        let aerosol = (-beta * lambda.powf(-alpha) * mass).exp();
+        rayleigh * aerosol
+    };
+
+    Vec3::new(channel(0.68), channel(0.55), channel(0.44))
+}
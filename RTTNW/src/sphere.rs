@@ -1,6 +1,7 @@
 use std::ops::Range;
 use std::sync::Arc;
 use ultraviolet::Vec3;
+use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
@@ -8,12 +9,19 @@ use crate::ray::Ray;
 pub struct Sphere {
     centre: Vec3,
     radius: f32,
-    material: Arc<dyn Material>
+    material: Arc<dyn Material>,
+    material_id: u32,
 }
 
 impl Sphere {
     pub fn new(centre: Vec3, radius: f32, material: Arc<dyn Material>) -> Self {
-        Sphere { centre, radius: radius.max(0.0), material }
+        Sphere { centre, radius: radius.max(0.0), material, material_id: 0 }
+    }
+
+    pub fn with_material_id(mut self, id: u32) -> Self {
+        // the number the scene gave the material, for the material id aov
+        self.material_id = id;
+        self
     }
 
     pub fn get_face_normal(r: &Ray, out_norm: Vec3) -> (bool, Vec3) {
@@ -55,32 +63,57 @@ impl Hittable for Sphere {
             normal: out_norm,
             time: root,
             front_face,
-            material: self.material.clone()
+            local_normal: norm,
+            material: self.material.clone(),
+            material_id: self.material_id,
+            object_id: None,
         })
 
     }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::around(self.centre, self.radius)
+    }
 }
 
 pub struct MovingSphere {
     center: Ray,
+    // at centre_0 until time.start, then moving steadily to centre_1 at time.end
+    time: Range<f32>,
     radius: f32,
-    material: Arc<dyn Material>
+    material: Arc<dyn Material>,
+    material_id: u32,
 }
 
 impl MovingSphere {
     pub(crate) fn new(centre_0: Vec3, centre_1: Vec3, radius: f32, material: Arc<dyn Material>) -> Self {
         MovingSphere {
             center: Ray::new(centre_0, centre_1 - centre_0, 0.0),
+            time: 0.0..1.0,
             radius: radius.max(0.0),
-            material
+            material,
+            material_id: 0,
         }
     }
+
+    pub fn with_material_id(mut self, id: u32) -> Self {
+        self.material_id = id;
+        self
+    }
+
+    pub fn with_time_range(mut self, time: Range<f32>) -> Self {
+        // moves over time instead of from 0 to 1, such as the frames of an animation
+        self.time = time;
+        self
+    }
 }
 
 impl Hittable for MovingSphere {
     fn hit(&self, ray: &Ray, t_interval: Range<f32>) -> Option<HitRecord> {
 
-        let curr_centre = self.center.at(ray.time);
+        let duration = self.time.end - self.time.start;
+        let s = if duration > 0.0 { ((ray.time - self.time.start) / duration).clamp(0.0, 1.0) } else { 1.0 };
+        let curr_centre = self.center.at(s);
         let oc = curr_centre - ray.origin;
         let a = ray.direction.mag_sq();
         let h = ray.direction.dot(oc);
@@ -108,7 +141,15 @@ impl Hittable for MovingSphere {
             normal: out_norm,
             time: root,
             front_face,
-            material: self.material.clone()
+            local_normal: norm,
+            material: self.material.clone(),
+            material_id: self.material_id,
+            object_id: None,
         })
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Aabb {
+        // the straight path between the two ends stays inside their boxes joined
+        Aabb::around(self.center.at(0.0), self.radius).union(&Aabb::around(self.center.at(1.0), self.radius))
+    }
+}
@@ -0,0 +1,161 @@
+use std::str::FromStr;
+use ultraviolet::{Mat3, Vec3};
+
+// display transforms applied to the linear film before it is quantised to 8 bits.
+// every operator maps scene-referred linear rgb to display-referred linear rgb in 0..1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    // white is the smallest value that maps to 1
+    ReinhardExtended { white: f32 },
+    Aces,
+    AgX,
+    Uncharted2,
+}
+
+impl ToneMap {
+
+    pub fn apply(&self, col: Vec3) -> Vec3 {
+        let col = col.max_by_component(Vec3::zero());
+
+        let out = match *self {
+            ToneMap::Clamp => col,
+            ToneMap::Reinhard => col.map(|x| x / (1.0 + x)),
+            ToneMap::ReinhardExtended { white } =>
+                col.map(|x| x * (1.0 + x / (white * white)) / (1.0 + x)),
+            ToneMap::Aces => aces(col),
+            ToneMap::AgX => agx(col),
+            ToneMap::Uncharted2 => {
+                let exposure_bias = 2.0;
+                let white = 11.2;
+                (exposure_bias * col).map(hable) / hable(white)
+            }
+        };
+
+        out.clamped(Vec3::zero(), Vec3::one())
+    }
+
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "reinhard-extended" => Ok(ToneMap::ReinhardExtended { white: 4.0 }),
+            "aces" => Ok(ToneMap::Aces),
+            "agx" => Ok(ToneMap::AgX),
+            "uncharted2" => Ok(ToneMap::Uncharted2),
+            _ => Err(format!("unknown tone map '{s}'"))
+        }
+    }
+}
+
+pub fn srgb_encode(x: f32) -> f32 {
+    // sRGB opto-electronic transfer function, for linear x in 0..1
+    if x <= 0.003_130_8 { 12.92 * x } else { 1.055 * x.powf(1.0 / 2.4) - 0.055 }
+}
+
+pub fn srgb_decode(x: f32) -> f32 {
+    // inverse of srgb_encode
+    if x <= 0.040_45 { x / 12.92 } else { ((x + 0.055) / 1.055).powf(2.4) }
+}
+
+fn aces(col: Vec3) -> Vec3 {
+    // Stephen Hill's fit of the ACES RRT + sRGB ODT, including the conversions
+    // to and from the ACES AP1 space
+
+    let input = Mat3::new(
+        Vec3::new(0.597_19, 0.076_00, 0.028_40),
+        Vec3::new(0.354_58, 0.908_34, 0.133_83),
+        Vec3::new(0.048_23, 0.015_66, 0.837_77),
+    );
+    let output = Mat3::new(
+        Vec3::new(1.604_75, -0.102_08, -0.003_27),
+        Vec3::new(-0.531_08, 1.108_13, -0.072_76),
+        Vec3::new(-0.073_67, -0.006_05, 1.076_02),
+    );
+
+    let v = input * col;
+    let rrt_odt = v.map(|x| (x * (x + 0.024_578_6) - 0.000_090_537) / (x * (0.983_729 * x + 0.432_951) + 0.238_081));
+
+    output * rrt_odt
+}
+
+fn agx(col: Vec3) -> Vec3 {
+    // Troy Sobotka's AgX base look, using the common polynomial fit of the sigmoid
+
+    let inset = Mat3::new(
+        Vec3::new(0.842_479_06, 0.042_328_24, 0.042_375_65),
+        Vec3::new(0.078_433_6, 0.878_468_64, 0.078_433_6),
+        Vec3::new(0.079_223_75, 0.079_166_13, 0.879_143),
+    );
+    let outset = Mat3::new(
+        Vec3::new(1.196_879, -0.052_896_85, -0.052_971_64),
+        Vec3::new(-0.098_020_88, 1.151_903_1, -0.098_043_45),
+        Vec3::new(-0.099_029_74, -0.098_961_18, 1.151_073_7),
+    );
+
+    let (min_ev, max_ev) = (-12.473_93, 4.026_069);
+
+    let v = (inset * col).map(|x| {
+        let ev = x.max(1e-10).log2().clamp(min_ev, max_ev);
+        let x = (ev - min_ev) / (max_ev - min_ev);
+
+        15.5 * x.powi(6) - 40.14 * x.powi(5) + 31.96 * x.powi(4)
+            - 6.868 * x.powi(3) + 0.4298 * x.powi(2) + 0.1191 * x - 0.002_32
+    });
+
+    // the sigmoid output is display encoded, so undo the 2.2 gamma to stay linear
+    (outset * v).map(|x| x.max(0.0).powf(2.2))
+}
+
+fn hable(x: f32) -> f32 {
+    // John Hable's Uncharted 2 filmic curve
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    // ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f, over a common
+    // denominator so black stays exactly black
+    x * (f * (a * x + c * b) - e * (a * x + b)) / (f * (x * (a * x + b) + d * f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE_MAPS: [&str; 6] = ["clamp", "reinhard", "reinhard-extended", "aces", "agx", "uncharted2"];
+
+    #[test]
+    fn operators_map_black_to_black() {
+        for name in TONE_MAPS {
+            let tone_map: ToneMap = name.parse().unwrap();
+            assert_eq!(tone_map.apply(Vec3::zero()), Vec3::zero(), "{name}");
+        }
+    }
+
+    #[test]
+    fn operators_are_monotonic_and_in_range() {
+        // exposure ramps of a grey and a few saturated colours, from 1e-4 to 1e4, brighten
+        // in every channel. AgX is let off for the colours: once their strongest channel
+        // reaches the top of its curve, its outset matrix dims that channel a little as the
+        // others catch up, on purpose, to desaturate towards white
+        let colours = [Vec3::one(), Vec3::new(1.0, 0.2, 0.1), Vec3::new(0.1, 0.8, 0.3), Vec3::new(0.05, 0.1, 1.0)];
+
+        for (name, colour) in TONE_MAPS.iter().flat_map(|name| colours.map(|colour| (name, colour))) {
+            let tone_map: ToneMap = name.parse().unwrap();
+            let mut last = Vec3::zero();
+
+            for i in 0..=400 {
+                let col = tone_map.apply(colour * 10f32.powf(-4.0 + i as f32 / 50.0));
+
+                assert!(col.component_min() >= 0.0 && col.component_max() <= 1.0, "{name} gives {col:?}");
+                if tone_map != ToneMap::AgX || colour == Vec3::one() {
+                    assert!((col - last).component_min() >= 0.0, "{name} falls from {last:?} to {col:?}");
+                }
+                last = col;
+            }
+        }
+    }
+}